@@ -16,9 +16,10 @@
 use std::env;
 use std::fs;
 use std::io::{self, Write};
+use std::path::Path;
 use std::process;
 
-use yarel::error::ErrorKind;
+use yarel::error::{Error, ErrorKind};
 use yarel::vm::{self, Vm};
 
 fn repl(vm: &mut Vm) {
@@ -33,9 +34,8 @@ fn repl(vm: &mut Vm) {
                     println!();
                     process::exit(0);
                 }
-                match vm::interpret(vm, buffer, None) {
-                    Ok(_) => {}
-                    Err(error) => eprint!("{}", error),
+                if let Err(error) = vm::interpret(vm, buffer, None) {
+                    report_error(vm, &error);
                 }
             }
             _ => {
@@ -59,22 +59,48 @@ fn run_file(vm: &mut Vm, path: &str) {
         } else {
             70
         };
-        eprint!("{}", error);
+        report_error(vm, &error);
         process::exit(exit_code);
     }
 }
 
+fn report_error(vm: &mut Vm, error: &Error) {
+    let error_output = vm.error_output();
+    let _ = write!(error_output, "{}", error);
+    let _ = error_output.flush();
+}
+
+// Modules are searched for relative to the provided directory and then in each of the
+// directories listed in the YAREL_PATH environment variable.
+fn set_search_paths(vm: &mut Vm, directory: &Path) {
+    let mut paths = vec![directory.to_string_lossy().into_owned()];
+    if let Some(yarel_path) = env::var_os("YAREL_PATH") {
+        paths.extend(env::split_paths(&yarel_path).map(|p| p.to_string_lossy().into_owned()));
+    }
+    vm.set_search_paths(paths);
+}
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
 
     let mut vm = Vm::with_built_ins();
 
+    if let Some(pos) = args.iter().position(|a| a == "--strip-asserts") {
+        args.remove(pos);
+        vm.set_strip_asserts(true);
+    }
+
     if args.len() == 1 {
+        set_search_paths(&mut vm, Path::new(""));
         repl(&mut vm);
     } else if args.len() == 2 {
+        let directory = Path::new(&args[1])
+            .parent()
+            .unwrap_or_else(|| Path::new(""));
+        set_search_paths(&mut vm, directory);
         run_file(&mut vm, &args[1]);
     } else {
-        eprintln!("Usage: ./yarel-cli [path]");
+        eprintln!("Usage: ./yarel-cli [--strip-asserts] [path]");
         process::exit(64);
     }
 }
@@ -54,7 +54,7 @@ fn get_paths(root: &Path, suffix: Option<&str>) -> Result<Vec<String>, ()> {
         let path = entry.map_err(|_| ())?.path();
         let path_as_str = path.as_path().to_str().ok_or(())?;
         if path.is_dir() {
-            paths.extend_from_slice(&get_paths(&Path::new(path_as_str), suffix)?);
+            paths.extend_from_slice(&get_paths(Path::new(path_as_str), suffix)?);
         } else if suffix.is_none() {
             paths.push(path_as_str.to_owned());
         } else if let Some(suffix) = suffix {
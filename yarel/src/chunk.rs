@@ -24,6 +24,7 @@ pub enum OpCode {
     False,
     Pop,
     CopyTop,
+    CopyTopTwo,
     GetLocal,
     SetLocal,
     GetGlobal,
@@ -38,6 +39,7 @@ pub enum OpCode {
     Equal,
     Greater,
     Less,
+    Contains,
     Add,
     Subtract,
     Multiply,
@@ -53,6 +55,7 @@ pub enum OpCode {
     IterNext,
     Jump,
     JumpIfFalse,
+    JumpIfNil,
     JumpIfSentinel,
     Loop,
     Call,
@@ -61,13 +64,20 @@ pub enum OpCode {
     Closure,
     CloseUpvalue,
     Return,
+    EnterWith,
+    ExitWith,
+    Assert,
+    AssertCompare,
     DeclareClass,
     DefineClass,
     Inherit,
+    Metaclass,
+    ClassDoc,
     Method,
     StaticMethod,
     StartImport,
     FinishImport,
+    ImportFrom,
 }
 
 impl OpCode {
@@ -79,6 +89,7 @@ impl OpCode {
             OpCode::False => &[],
             OpCode::Pop => &[],
             OpCode::CopyTop => &[],
+            OpCode::CopyTopTwo => &[],
             OpCode::GetLocal => &[1],
             OpCode::SetLocal => &[1],
             OpCode::GetGlobal => &[2],
@@ -93,6 +104,7 @@ impl OpCode {
             OpCode::Equal => &[],
             OpCode::Greater => &[],
             OpCode::Less => &[],
+            OpCode::Contains => &[],
             OpCode::Add => &[],
             OpCode::Subtract => &[],
             OpCode::Multiply => &[],
@@ -101,13 +113,14 @@ impl OpCode {
             OpCode::Negate => &[],
             OpCode::FormatString => &[],
             OpCode::BuildHashMap => &[1],
-            OpCode::BuildRange => &[],
+            OpCode::BuildRange => &[1],
             OpCode::BuildString => &[1],
             OpCode::BuildTuple => &[1],
             OpCode::BuildVec => &[1],
             OpCode::IterNext => &[],
             OpCode::Jump => &[2],
             OpCode::JumpIfFalse => &[2],
+            OpCode::JumpIfNil => &[2],
             OpCode::JumpIfSentinel => &[2],
             OpCode::Loop => &[2],
             OpCode::Call => &[1],
@@ -116,13 +129,20 @@ impl OpCode {
             OpCode::Closure => &[2],
             OpCode::CloseUpvalue => &[],
             OpCode::Return => &[],
+            OpCode::EnterWith => &[1],
+            OpCode::ExitWith => &[],
+            OpCode::Assert => &[],
+            OpCode::AssertCompare => &[2],
             OpCode::DeclareClass => &[2],
             OpCode::DefineClass => &[],
             OpCode::Inherit => &[],
+            OpCode::Metaclass => &[],
+            OpCode::ClassDoc => &[2],
             OpCode::Method => &[2],
             OpCode::StaticMethod => &[2],
             OpCode::StartImport => &[2],
             OpCode::FinishImport => &[],
+            OpCode::ImportFrom => &[2],
         }
     }
 }
@@ -136,6 +156,7 @@ impl From<u8> for OpCode {
             value if value == OpCode::False as u8 => OpCode::False,
             value if value == OpCode::Pop as u8 => OpCode::Pop,
             value if value == OpCode::CopyTop as u8 => OpCode::CopyTop,
+            value if value == OpCode::CopyTopTwo as u8 => OpCode::CopyTopTwo,
             value if value == OpCode::GetLocal as u8 => OpCode::GetLocal,
             value if value == OpCode::SetLocal as u8 => OpCode::SetLocal,
             value if value == OpCode::GetGlobal as u8 => OpCode::GetGlobal,
@@ -150,6 +171,7 @@ impl From<u8> for OpCode {
             value if value == OpCode::Equal as u8 => OpCode::Equal,
             value if value == OpCode::Greater as u8 => OpCode::Greater,
             value if value == OpCode::Less as u8 => OpCode::Less,
+            value if value == OpCode::Contains as u8 => OpCode::Contains,
             value if value == OpCode::Add as u8 => OpCode::Add,
             value if value == OpCode::Subtract as u8 => OpCode::Subtract,
             value if value == OpCode::Multiply as u8 => OpCode::Multiply,
@@ -165,6 +187,7 @@ impl From<u8> for OpCode {
             value if value == OpCode::IterNext as u8 => OpCode::IterNext,
             value if value == OpCode::Jump as u8 => OpCode::Jump,
             value if value == OpCode::JumpIfFalse as u8 => OpCode::JumpIfFalse,
+            value if value == OpCode::JumpIfNil as u8 => OpCode::JumpIfNil,
             value if value == OpCode::JumpIfSentinel as u8 => OpCode::JumpIfSentinel,
             value if value == OpCode::Loop as u8 => OpCode::Loop,
             value if value == OpCode::Call as u8 => OpCode::Call,
@@ -173,18 +196,32 @@ impl From<u8> for OpCode {
             value if value == OpCode::Closure as u8 => OpCode::Closure,
             value if value == OpCode::CloseUpvalue as u8 => OpCode::CloseUpvalue,
             value if value == OpCode::Return as u8 => OpCode::Return,
+            value if value == OpCode::EnterWith as u8 => OpCode::EnterWith,
+            value if value == OpCode::ExitWith as u8 => OpCode::ExitWith,
+            value if value == OpCode::Assert as u8 => OpCode::Assert,
+            value if value == OpCode::AssertCompare as u8 => OpCode::AssertCompare,
             value if value == OpCode::DeclareClass as u8 => OpCode::DeclareClass,
             value if value == OpCode::DefineClass as u8 => OpCode::DefineClass,
             value if value == OpCode::Inherit as u8 => OpCode::Inherit,
+            value if value == OpCode::Metaclass as u8 => OpCode::Metaclass,
+            value if value == OpCode::ClassDoc as u8 => OpCode::ClassDoc,
             value if value == OpCode::Method as u8 => OpCode::Method,
             value if value == OpCode::StaticMethod as u8 => OpCode::StaticMethod,
             value if value == OpCode::StartImport as u8 => OpCode::StartImport,
             value if value == OpCode::FinishImport as u8 => OpCode::FinishImport,
+            value if value == OpCode::ImportFrom as u8 => OpCode::ImportFrom,
             _ => panic!("Unknown opcode {}", value),
         }
     }
 }
 
+/// Flags encoded in the operand of `OpCode::BuildRange`, describing which range components were
+/// pushed onto the stack.
+pub(crate) const RANGE_HAS_BEGIN: u8 = 0b0001;
+pub(crate) const RANGE_HAS_END: u8 = 0b0010;
+pub(crate) const RANGE_HAS_STEP: u8 = 0b0100;
+pub(crate) const RANGE_INCLUSIVE: u8 = 0b1000;
+
 #[derive(Clone, Default)]
 pub struct Chunk {
     pub code: Vec<u8>,
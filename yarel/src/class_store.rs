@@ -358,6 +358,7 @@ pub(crate) unsafe fn new_base_metaclass(vm: &mut Vm) -> GcBoxPtr<ObjClass> {
         metaclass: Gc::dangling(),
         superclass: None,
         methods: object::new_obj_string_value_map(),
+        doc: None,
     };
     let mut ptr = vm.allocate_bare(data);
     let root = Root::from(ptr);
@@ -18,7 +18,7 @@ use std::fmt::Write;
 use std::mem;
 use std::path::Path;
 
-use crate::chunk::{Chunk, OpCode};
+use crate::chunk::{self, Chunk, OpCode};
 use crate::common;
 use crate::debug;
 use crate::error::{Error, ErrorKind};
@@ -32,6 +32,7 @@ use crate::vm::Vm;
 enum Precedence {
     None,
     Assignment,
+    NilCoalesce,
     Or,
     And,
     Equality,
@@ -49,6 +50,7 @@ impl From<usize> for Precedence {
         match value {
             value if value == Precedence::None as usize => Precedence::None,
             value if value == Precedence::Assignment as usize => Precedence::Assignment,
+            value if value == Precedence::NilCoalesce as usize => Precedence::NilCoalesce,
             value if value == Precedence::Or as usize => Precedence::Or,
             value if value == Precedence::And as usize => Precedence::And,
             value if value == Precedence::Equality as usize => Precedence::Equality,
@@ -64,28 +66,20 @@ impl From<usize> for Precedence {
     }
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, Default, PartialEq)]
 enum FunctionKind {
     Function,
     Initialiser,
     Method,
+    #[default]
     Script,
     StaticMethod,
+    UnboundMethod,
 }
 
 impl FunctionKind {
     fn is_bound(&self) -> bool {
-        match self {
-            FunctionKind::Initialiser => true,
-            FunctionKind::Method => true,
-            _ => false,
-        }
-    }
-}
-
-impl Default for FunctionKind {
-    fn default() -> Self {
-        FunctionKind::Script
+        matches!(self, FunctionKind::Initialiser | FunctionKind::Method)
     }
 }
 
@@ -121,6 +115,8 @@ struct Compiler {
     upvalues: Vec<Upvalue>,
     scope_depth: usize,
     lambda_count: usize,
+    with_depth: usize,
+    doc: Option<String>,
 }
 
 enum CompilerError {
@@ -140,7 +136,7 @@ impl Compiler {
             locals: vec![Local {
                 name: if kind == FunctionKind::StaticMethod {
                     "Self"
-                } else if kind != FunctionKind::Function {
+                } else if !matches!(kind, FunctionKind::Function | FunctionKind::UnboundMethod) {
                     "self"
                 } else {
                     ""
@@ -152,6 +148,8 @@ impl Compiler {
             upvalues: Vec::new(),
             scope_depth: 0,
             lambda_count: 0,
+            with_depth: 0,
+            doc: None,
         }
     }
 
@@ -160,6 +158,7 @@ impl Compiler {
         let num_upvalues = self.upvalues.len();
         let chunk = mem::replace(&mut self.chunk, Chunk::new());
         let chunk_index = vm.add_chunk(chunk);
+        let doc = self.doc.as_deref().map(|doc| vm.new_gc_obj_string(doc));
         object::new_root_obj_function(
             vm,
             name,
@@ -167,6 +166,7 @@ impl Compiler {
             num_upvalues,
             chunk_index,
             module_path,
+            doc,
         )
     }
 
@@ -219,6 +219,15 @@ struct ClassCompiler {
     has_superclass: bool,
 }
 
+/// Bytecode for the decorator expressions preceding a declaration. The code is held back so
+/// that it can be emitted at the point where the decorated value is built.
+#[derive(Default)]
+struct Decorators {
+    code: Vec<u8>,
+    lines: Vec<i32>,
+    count: usize,
+}
+
 pub fn compile(
     vm: &mut Vm,
     source: String,
@@ -239,6 +248,11 @@ struct Parser<'a> {
     class_compilers: Vec<ClassCompiler>,
     errors: RefCell<Vec<String>>,
     compiled_functions: Vec<Root<ObjFunction>>,
+    optional_chain_jumps: Vec<usize>,
+    expr_depth: usize,
+    comparison: Option<(usize, &'static str)>,
+    class_name_hint: Option<String>,
+    doc_comment: Option<String>,
     module_path: Gc<ObjString>,
     vm: &'a mut Vm,
 }
@@ -256,6 +270,11 @@ impl<'a> Parser<'a> {
             class_compilers: Vec::new(),
             errors: RefCell::new(Vec::new()),
             compiled_functions: Vec::new(),
+            optional_chain_jumps: Vec::new(),
+            expr_depth: 0,
+            comparison: None,
+            class_name_hint: None,
+            doc_comment: None,
             module_path,
             vm,
         };
@@ -266,8 +285,10 @@ impl<'a> Parser<'a> {
     fn parse(&mut self) -> Result<Root<ObjFunction>, Error> {
         self.advance();
 
+        self.skip_newlines();
         while !self.match_token(TokenKind::Eof) {
             self.declaration();
+            self.skip_newlines();
         }
 
         let had_error = !self.errors.borrow().is_empty();
@@ -283,6 +304,7 @@ impl<'a> Parser<'a> {
             ));
         }
 
+        self.compiler_mut().doc = self.scanner.take_module_doc();
         Ok(self.finalise_compiler().0)
     }
 
@@ -308,6 +330,31 @@ impl<'a> Parser<'a> {
         self.error_at_current(message);
     }
 
+    // Statements end with a semicolon or a significant newline, either of which may be omitted
+    // before a closing brace or the end of the source.
+    fn match_terminator(&mut self) -> bool {
+        self.match_token(TokenKind::SemiColon)
+            || self.match_token(TokenKind::Newline)
+            || self.check_any(&[TokenKind::RightBrace, TokenKind::Eof])
+    }
+
+    fn consume_terminator(&mut self, message: &str) {
+        if !self.match_terminator() {
+            self.error_at_current(message);
+        }
+    }
+
+    // The opening brace of a body may be on the line after the statement or declaration it
+    // belongs to.
+    fn consume_body_brace(&mut self, message: &str) {
+        self.skip_newlines();
+        self.consume(TokenKind::LeftBrace, message);
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.match_token(TokenKind::Newline) {}
+    }
+
     fn check(&self, kind: TokenKind) -> bool {
         self.current.kind == kind
     }
@@ -341,8 +388,10 @@ impl<'a> Parser<'a> {
     }
 
     fn block(&mut self) {
+        self.skip_newlines();
         while !self.check(TokenKind::RightBrace) && !self.check(TokenKind::Eof) {
             self.declaration();
+            self.skip_newlines();
         }
 
         self.consume(TokenKind::RightBrace, "Expected '}' after block.");
@@ -368,9 +417,11 @@ impl<'a> Parser<'a> {
         (function, compiler.upvalues)
     }
 
-    fn function(&mut self, kind: FunctionKind) {
+    fn function(&mut self, kind: FunctionKind) -> u32 {
         let name = self.previous.source.clone();
+        let doc = self.doc_comment.take();
         self.new_compiler(kind, name.as_str());
+        self.compiler_mut().doc = doc;
         self.begin_scope();
 
         self.consume(TokenKind::LeftParen, "Expected '(' after function name.");
@@ -380,6 +431,16 @@ impl<'a> Parser<'a> {
                 "Expected 'self' as first parameter in method.",
             );
             self.match_token(TokenKind::Comma);
+        } else if kind == FunctionKind::UnboundMethod {
+            // The receiver of an unbound method is an ordinary parameter named 'self'.
+            self.consume(
+                TokenKind::Self_,
+                "Expected 'self' as first parameter in method.",
+            );
+            self.compiler_mut().func_arity += 1;
+            self.compiler_mut().add_local(&Token::from_string("self"));
+            self.mark_initialised();
+            self.match_token(TokenKind::Comma);
         } else if self.match_token(TokenKind::Self_) {
             self.error("Expected parameter name.");
             self.match_token(TokenKind::Comma);
@@ -391,21 +452,28 @@ impl<'a> Parser<'a> {
         );
         self.consume(TokenKind::RightParen, "Expected ')' after parameters.");
 
-        self.consume(TokenKind::LeftBrace, "Expected '{' before function body.");
+        self.consume_body_brace("Expected '{' before function body.");
         self.block();
 
+        let arity = self.compiler().func_arity;
         let (function, upvalues) = self.finalise_compiler();
+        self.emit_closure(function, upvalues);
+        arity
+    }
 
+    fn emit_closure(&mut self, function: Root<ObjFunction>, upvalues: Vec<Upvalue>) {
         let constant = self.make_constant(value::Value::ObjFunction(function.as_gc()));
         self.emit_constant_op(OpCode::Closure, constant);
 
         for upvalue in upvalues.iter() {
             self.emit_byte(upvalue.is_local as u8);
-            self.emit_byte(upvalue.index as u8);
+            self.emit_byte(upvalue.index);
         }
     }
 
     fn method(&mut self) {
+        self.doc_comment = self.current.doc.clone();
+        let decorators = self.decorators();
         let first_token = self.current.clone();
         let static_method = self.match_token(TokenKind::Static);
         self.consume(TokenKind::Fn, "Expected 'fn' before method name.");
@@ -423,7 +491,13 @@ impl<'a> Parser<'a> {
         } else {
             FunctionKind::Method
         };
+        if decorators.count > 0 && kind.is_bound() {
+            self.decorated_method(kind, &decorators, constant);
+            return;
+        }
+        self.emit_decorators(&decorators);
         self.function(kind);
+        self.apply_decorators(&decorators);
         let opcode = if static_method {
             OpCode::StaticMethod
         } else {
@@ -432,14 +506,99 @@ impl<'a> Parser<'a> {
         self.emit_constant_op(opcode, constant);
     }
 
-    fn class_declaration(&mut self) {
+    // A decorated method is compiled as an unbound function taking the receiver as its first
+    // argument, so that decorators can wrap it like any other function. The method itself is a
+    // closure over the decorated value that passes the receiver on along with its arguments.
+    fn decorated_method(&mut self, kind: FunctionKind, decorators: &Decorators, constant: u16) {
+        let name = self.previous.source.clone();
+        let doc = self.doc_comment.clone();
+
+        self.begin_scope();
+        self.emit_decorators(decorators);
+        let arity = self.function(FunctionKind::UnboundMethod);
+        self.apply_decorators(decorators);
+        let decorated = Token::from_string("... decorated method ...");
+        if !self.compiler_mut().add_local(&decorated) {
+            self.error("Too many variables in function.");
+        }
+        self.mark_initialised();
+
+        // The method takes the same arguments as the unbound function, less the unbound
+        // function's own slot.
+        let num_args = arity - 1;
+        self.new_compiler(kind, name.as_str());
+        self.compiler_mut().doc = doc;
+        self.compiler_mut().func_arity = num_args;
+        self.named_variable(decorated, false);
+        for slot in 0..num_args {
+            self.emit_bytes([OpCode::GetLocal as u8, slot as u8]);
+        }
+        self.emit_bytes([OpCode::Call as u8, num_args as u8]);
+        if kind == FunctionKind::Initialiser {
+            self.emit_byte(OpCode::Pop as u8);
+        } else {
+            self.emit_byte(OpCode::Return as u8);
+        }
+
+        let (function, upvalues) = self.finalise_compiler();
+        self.emit_closure(function, upvalues);
+        self.emit_constant_op(OpCode::Method, constant);
+        self.end_scope();
+    }
+
+    fn class_declaration(&mut self, decorators: Decorators) {
         self.consume(TokenKind::Identifier, "Expected class name.");
         let name = self.previous.clone();
-        let name_constant = self.identifier_constant(&name);
         self.declare_variable();
+        self.class_definition(name.clone(), &name, decorators);
+    }
+
+    fn class_expression(s: &mut Parser, _can_assign: bool) {
+        let display_name = if s.check(TokenKind::Identifier) && !s.check_metaclass() {
+            s.advance();
+            s.previous.source.clone()
+        } else {
+            s.class_name_hint
+                .take()
+                .unwrap_or_else(|| String::from("Anonymous"))
+        };
+
+        // The class is defined inside an implicit function that is called immediately, so that
+        // the class can be bound to a local while its body is compiled without disturbing any
+        // temporaries on the stack.
+        let lambda_count = s.compiler().lambda_count;
+        s.compiler_mut().lambda_count += 1;
+        s.new_compiler(
+            FunctionKind::Function,
+            format!("class-{}", lambda_count).as_str(),
+        );
+        s.begin_scope();
+
+        let name = Token::from_string("... anonymous class ...");
+        s.compiler_mut().add_local(&name);
+        s.class_definition(
+            name.clone(),
+            &Token::from_string(&display_name),
+            Decorators::default(),
+        );
+        s.named_variable(name, false);
+        s.emit_byte(OpCode::Return as u8);
+
+        let (function, upvalues) = s.finalise_compiler();
+        s.emit_closure(function, upvalues);
+        s.emit_bytes([OpCode::Call as u8, 0]);
+    }
 
+    fn class_definition(&mut self, name: Token, display_name: &Token, decorators: Decorators) {
+        let doc = self.doc_comment.take();
+        let name_constant = self.identifier_constant(display_name);
         self.emit_constant_op(OpCode::DeclareClass, name_constant);
         self.define_variable(name_constant);
+        if let Some(doc) = doc {
+            let doc = self.vm.new_gc_obj_string(&doc);
+            let doc_constant = self.make_constant(Value::ObjString(doc));
+            self.emit_constant_op(OpCode::ClassDoc, doc_constant);
+        }
 
         self.class_compilers.push(ClassCompiler {
             has_superclass: false,
@@ -462,14 +621,32 @@ impl<'a> Parser<'a> {
             self.class_compilers.last_mut().unwrap().has_superclass = true;
         }
 
+        if self.check_metaclass() {
+            self.advance();
+            self.consume(TokenKind::Identifier, "Expected metaclass name.");
+            Parser::variable(self, false);
+            self.emit_byte(OpCode::Metaclass as u8);
+        }
+
         let (_, set_op, arg) = self.resolve_variable(&name);
 
-        self.named_variable(name, false);
-        self.consume(TokenKind::LeftBrace, "Expected '{' before class body.");
+        self.named_variable(name.clone(), false);
+        // The class remains on the stack while its body is compiled, so it is tracked as a local
+        // to keep the slots of any locals declared by its methods in step.
+        let class_local = Token::from_string("... class ...");
+        if !self.compiler_mut().add_local(&class_local) {
+            self.error("Too many variables in function.");
+        }
+        let scope_depth = self.compiler().scope_depth;
+        self.compiler_mut().locals.last_mut().unwrap().depth = Some(scope_depth);
+        self.consume_body_brace("Expected '{' before class body.");
+        self.skip_newlines();
         while !self.check(TokenKind::RightBrace) && !self.check(TokenKind::Eof) {
             self.method();
+            self.skip_newlines();
         }
         self.consume(TokenKind::RightBrace, "Expected '}' after class body.");
+        self.compiler_mut().locals.pop();
         self.emit_byte(OpCode::DefineClass as u8);
         self.emit_variable_op(set_op, arg);
         self.emit_byte(OpCode::Pop as u8);
@@ -478,35 +655,92 @@ impl<'a> Parser<'a> {
             self.end_scope();
         }
 
+        // Class decorators are only evaluated once the class has been defined, as the class body
+        // refers to the class through its variable.
+        if decorators.count > 0 {
+            let (_, set_op, arg) = self.resolve_variable(&name);
+            self.emit_decorators(&decorators);
+            self.named_variable(name, false);
+            self.apply_decorators(&decorators);
+            self.emit_variable_op(set_op, arg);
+            self.emit_byte(OpCode::Pop as u8);
+        }
+
         self.class_compilers.pop();
     }
 
-    fn fn_declaration(&mut self) {
+    fn fn_declaration(&mut self, decorators: Decorators) {
         let global = self.parse_variable("Expected function name.");
         self.mark_initialised();
+
+        // A decorated local function reserves its slot before the decorators are called, so that
+        // any upvalue referring to the function is not closed when a decorator returns.
+        if decorators.count > 0 && self.compiler().scope_depth > 0 {
+            self.emit_byte(OpCode::Nil as u8);
+            self.emit_decorators(&decorators);
+            self.function(FunctionKind::Function);
+            self.apply_decorators(&decorators);
+            let slot = (self.compiler().locals.len() - 1) as u8;
+            self.emit_bytes([OpCode::SetLocal as u8, slot]);
+            self.emit_byte(OpCode::Pop as u8);
+            return;
+        }
+
+        self.emit_decorators(&decorators);
         self.function(FunctionKind::Function);
+        self.apply_decorators(&decorators);
         self.define_variable(global);
     }
 
+    fn decorators(&mut self) -> Decorators {
+        let start = self.chunk().code.len();
+        let mut count = 0;
+        while self.match_token(TokenKind::At) {
+            self.expression();
+            self.skip_newlines();
+            count += 1;
+        }
+        let code = self.chunk().code.split_off(start);
+        let lines = self.chunk().lines.split_off(start);
+        Decorators { code, lines, count }
+    }
+
+    fn emit_decorators(&mut self, decorators: &Decorators) {
+        for (&byte, &line) in decorators.code.iter().zip(decorators.lines.iter()) {
+            self.chunk().write(byte, line);
+        }
+    }
+
+    // Decorators are applied innermost first, each call consuming the decorator below the value
+    // on the stack.
+    fn apply_decorators(&mut self, decorators: &Decorators) {
+        for _ in 0..decorators.count {
+            self.emit_bytes([OpCode::Call as u8, 1]);
+        }
+    }
+
     fn var_declaration(&mut self) {
         let global = self.parse_variable("Expected variable name.");
+        let name = self.previous.source.clone();
 
         if self.match_token(TokenKind::Equal) {
+            // An anonymous class assigned to a variable is named after the variable.
+            if self.check(TokenKind::Class) {
+                self.class_name_hint = Some(name);
+            }
             self.expression();
+            self.class_name_hint = None;
         } else {
             self.emit_byte(OpCode::Nil as u8);
         }
-        self.consume(
-            TokenKind::SemiColon,
-            "Expected ';' after variable declaration.",
-        );
+        self.consume_terminator("Expected ';' or newline after variable declaration.");
 
         self.define_variable(global);
     }
 
     fn expression_statement(&mut self) {
         self.expression();
-        self.consume(TokenKind::SemiColon, "Expected ';' after expression.");
+        self.consume_terminator("Expected ';' or newline after expression.");
         self.emit_byte(OpCode::Pop as u8);
     }
 
@@ -516,13 +750,13 @@ impl<'a> Parser<'a> {
         if path.source == "main" {
             self.error("Cannot import top-level module.");
         }
-        let path_constant = self.identifier_constant(&path);
+        let path_constant = self.identifier_constant(path);
 
         let name = if self.match_token(TokenKind::As) {
             self.consume(TokenKind::Identifier, "Expected module name.");
             self.previous.clone()
         } else {
-            let result = (|| Some(Path::new(&path.source).file_name()?.to_str()?))();
+            let result = (|| Path::new(&path.source).file_name()?.to_str())();
             if let Some(filename) = result {
                 Token::from_string_and_line(filename, self.current.line)
             } else {
@@ -536,7 +770,7 @@ impl<'a> Parser<'a> {
         self.declare_variable();
         self.emit_constant_op(OpCode::StartImport, path_constant);
 
-        self.consume(TokenKind::SemiColon, "Expected ';' after module import.");
+        self.consume_terminator("Expected ';' or newline after module import.");
 
         self.emit_byte(OpCode::FinishImport as u8);
 
@@ -544,6 +778,45 @@ impl<'a> Parser<'a> {
         self.define_variable(name_constant);
     }
 
+    fn selective_import_statement(&mut self) {
+        self.consume(TokenKind::Str, "Expected a module path.");
+        let path = self.previous.clone();
+        if path.source == "main" {
+            self.error("Cannot import top-level module.");
+        }
+        let path_constant = self.identifier_constant(&path);
+        self.consume(TokenKind::Import, "Expected 'import' after module path.");
+
+        loop {
+            self.consume(TokenKind::Identifier, "Expected name to import.");
+            let attribute = self.previous.clone();
+            let attribute_constant = self.identifier_constant(&attribute);
+            let name = if self.match_token(TokenKind::As) {
+                self.consume(TokenKind::Identifier, "Expected name after 'as'.");
+                self.previous.clone()
+            } else {
+                attribute
+            };
+
+            // Each name re-imports the module, which only executes the module's body the first
+            // time, so that the imported values occupy consecutive local slots.
+            self.previous = name.clone();
+            self.declare_variable();
+            self.emit_constant_op(OpCode::StartImport, path_constant);
+            self.emit_byte(OpCode::FinishImport as u8);
+            self.emit_constant_op(OpCode::ImportFrom, attribute_constant);
+
+            let name_constant = self.identifier_constant(&name);
+            self.define_variable(name_constant);
+
+            if !self.match_token(TokenKind::Comma) {
+                break;
+            }
+        }
+
+        self.consume_terminator("Expected ';' or newline after imported names.");
+    }
+
     fn for_statement(&mut self) {
         self.begin_scope();
 
@@ -595,7 +868,7 @@ impl<'a> Parser<'a> {
 
         self.emit_byte(OpCode::Pop as u8);
 
-        self.consume(TokenKind::LeftBrace, "Expected '{' after loop expression.");
+        self.consume_body_brace("Expected '{' after loop expression.");
         self.begin_scope();
         self.block();
         self.end_scope();
@@ -607,13 +880,51 @@ impl<'a> Parser<'a> {
         self.end_scope();
     }
 
+    fn with_statement(&mut self) {
+        self.begin_scope();
+
+        let manager_name = "... temp-with-manager ...";
+
+        // With statements take the following form:
+        // with manager as v {
+        //     ... body ...
+        // }
+        //
+        // The manager is held in a hidden local so that the VM can call its __exit__ method when
+        // the block is left, whether normally, by returning or by a runtime error.
+        self.expression();
+        self.compiler_mut()
+            .add_local(&Token::from_string(manager_name));
+        self.mark_initialised();
+        let manager_slot = self.compiler().locals.len() - 1;
+        self.emit_bytes([OpCode::EnterWith as u8, manager_slot as u8]);
+
+        if self.match_token(TokenKind::As) {
+            self.consume(TokenKind::Identifier, "Expected variable name after 'as'.");
+            self.declare_variable();
+            self.mark_initialised();
+        } else {
+            self.emit_byte(OpCode::Pop as u8);
+        }
+
+        self.consume_body_brace("Expected '{' after with expression.");
+        self.compiler_mut().with_depth += 1;
+        self.begin_scope();
+        self.block();
+        self.end_scope();
+        self.compiler_mut().with_depth -= 1;
+        self.emit_byte(OpCode::ExitWith as u8);
+
+        self.end_scope();
+    }
+
     fn if_statement(&mut self) {
         self.expression();
 
         let then_jump = self.emit_jump(OpCode::JumpIfFalse);
         self.emit_byte(OpCode::Pop as u8);
 
-        self.consume(TokenKind::LeftBrace, "Expected '{' after condition.");
+        self.consume_body_brace("Expected '{' after condition.");
         self.begin_scope();
         self.block();
         self.end_scope();
@@ -623,6 +934,7 @@ impl<'a> Parser<'a> {
         self.patch_jump(then_jump);
         self.emit_byte(OpCode::Pop as u8);
 
+        self.skip_newlines();
         if self.match_token(TokenKind::Else) {
             if !self.check_any(&[TokenKind::If, TokenKind::LeftBrace]) {
                 self.error_at_current("Expected '{' after 'else'.");
@@ -632,22 +944,83 @@ impl<'a> Parser<'a> {
         self.patch_jump(else_jump);
     }
 
+    fn assert_statement(&mut self) {
+        let start = self.chunk().code.len();
+
+        // Comparisons at the top level of the asserted expression keep copies of their operands
+        // so that both values can be reported if the assertion fails.
+        let expr_depth = mem::replace(&mut self.expr_depth, 0);
+        self.comparison = None;
+        self.expression();
+        self.expr_depth = expr_depth;
+        let comparison = self.comparison.take();
+
+        if let Some((offset, _)) = comparison {
+            let op_code = self.chunk().code.split_off(offset);
+            let op_lines = self.chunk().lines.split_off(offset);
+            self.emit_byte(OpCode::CopyTopTwo as u8);
+            for (byte, line) in op_code.into_iter().zip(op_lines) {
+                self.chunk().write(byte, line);
+            }
+        }
+
+        let fail_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_byte(OpCode::Pop as u8);
+        if comparison.is_some() {
+            self.emit_bytes([OpCode::Pop as u8, OpCode::Pop as u8]);
+        }
+        let end_jump = self.emit_jump(OpCode::Jump);
+
+        // The message is only evaluated if the assertion fails.
+        self.patch_jump(fail_jump);
+        if self.match_token(TokenKind::Comma) {
+            self.expression();
+        } else {
+            self.emit_byte(OpCode::Nil as u8);
+        }
+        match comparison {
+            Some((_, op)) => {
+                let op = Value::ObjString(self.vm.new_gc_obj_string(op));
+                let constant = self.make_constant(op);
+                self.emit_constant_op(OpCode::AssertCompare, constant);
+            }
+            None => self.emit_byte(OpCode::Assert as u8),
+        }
+        self.patch_jump(end_jump);
+
+        self.consume_terminator("Expected ';' or newline after assertion.");
+
+        if self.vm.strip_asserts() {
+            self.chunk().code.truncate(start);
+            self.chunk().lines.truncate(start);
+        }
+    }
+
     fn return_statement(&mut self) {
         if self.compiler().kind == FunctionKind::Script {
             self.error("Cannot return from top-level code.");
         }
-        if self.match_token(TokenKind::SemiColon) {
+        if self.match_terminator() {
+            self.emit_with_exits();
             self.emit_return();
         } else {
             if self.compiler().kind == FunctionKind::Initialiser {
                 self.error("Cannot return a value from an initialiser.");
             }
             self.expression();
-            self.consume(TokenKind::SemiColon, "Expected ';' after return value.");
+            self.consume_terminator("Expected ';' or newline after return value.");
+            self.emit_with_exits();
             self.emit_byte(OpCode::Return as u8);
         }
     }
 
+    // Returning from inside one or more with blocks exits each of them, innermost first.
+    fn emit_with_exits(&mut self) {
+        for _ in 0..self.compiler().with_depth {
+            self.emit_byte(OpCode::ExitWith as u8);
+        }
+    }
+
     fn while_statement(&mut self) {
         let loop_start = self.chunk().code.len();
 
@@ -657,7 +1030,7 @@ impl<'a> Parser<'a> {
 
         self.emit_byte(OpCode::Pop as u8);
 
-        self.consume(TokenKind::LeftBrace, "Expected '{' after condition.");
+        self.consume_body_brace("Expected '{' after condition.");
         self.begin_scope();
         self.block();
         self.end_scope();
@@ -672,18 +1045,23 @@ impl<'a> Parser<'a> {
         self.panic_mode.set(false);
 
         while self.current.kind != TokenKind::Eof {
-            if self.previous.kind == TokenKind::SemiColon {
+            if self.previous.kind == TokenKind::SemiColon
+                || self.previous.kind == TokenKind::Newline
+            {
                 return;
             }
 
             match self.current.kind {
+                TokenKind::At => return,
                 TokenKind::Class => return,
                 TokenKind::Fn => return,
                 TokenKind::Var => return,
                 TokenKind::For => return,
                 TokenKind::If => return,
                 TokenKind::While => return,
+                TokenKind::With => return,
                 TokenKind::Return => return,
+                TokenKind::Assert => return,
                 _ => {}
             }
 
@@ -724,6 +1102,10 @@ impl<'a> Parser<'a> {
     fn statement(&mut self) {
         if self.match_token(TokenKind::Import) {
             self.import_statement();
+        } else if self.match_token(TokenKind::From) {
+            self.selective_import_statement();
+        } else if self.match_token(TokenKind::Assert) {
+            self.assert_statement();
         } else if self.match_token(TokenKind::For) {
             self.for_statement();
         } else if self.match_token(TokenKind::If) {
@@ -732,6 +1114,8 @@ impl<'a> Parser<'a> {
             self.return_statement();
         } else if self.match_token(TokenKind::While) {
             self.while_statement();
+        } else if self.match_token(TokenKind::With) {
+            self.with_statement();
         } else if self.match_token(TokenKind::LeftBrace) {
             self.begin_scope();
             self.block();
@@ -741,11 +1125,26 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn declaration(&mut self) {
+    fn decorated_declaration(&mut self) {
+        let decorators = self.decorators();
         if self.match_token(TokenKind::Class) {
-            self.class_declaration();
+            self.class_declaration(decorators);
+        } else if self.match_token(TokenKind::Fn) {
+            self.fn_declaration(decorators);
+        } else {
+            self.error_at_current("Expected 'fn' or 'class' after decorator.");
+        }
+    }
+
+    fn declaration(&mut self) {
+        // Doc comments are attached to the first token of the declaration they document.
+        self.doc_comment = self.current.doc.clone();
+        if self.check(TokenKind::At) {
+            self.decorated_declaration();
+        } else if self.match_token(TokenKind::Class) {
+            self.class_declaration(Decorators::default());
         } else if self.match_token(TokenKind::Fn) {
-            self.fn_declaration();
+            self.fn_declaration(Decorators::default());
         } else if self.match_token(TokenKind::Var) {
             self.var_declaration();
         } else {
@@ -773,7 +1172,7 @@ impl<'a> Parser<'a> {
     }
 
     fn emit_variable_op(&mut self, opcode: OpCode, variable: u16) {
-        if opcode.arg_sizes() == &[1] {
+        if opcode.arg_sizes() == [1] {
             self.emit_bytes([opcode as u8, variable as u8]);
         } else {
             self.emit_constant_op(opcode, variable);
@@ -838,10 +1237,17 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_precedence(&mut self, precedence: Precedence) {
+        self.expr_depth += 1;
+        self.parse_precedence_inner(precedence);
+        self.expr_depth -= 1;
+    }
+
+    fn parse_precedence_inner(&mut self, precedence: Precedence) {
         self.advance();
         let kind = self.previous.kind;
         let prefix_rule = self.get_rule(kind).prefix;
         let can_assign = precedence as usize <= Precedence::Assignment as usize;
+        let chain_start = self.optional_chain_jumps.len();
 
         match prefix_rule {
             Some(ref handler) => handler(self, can_assign),
@@ -852,16 +1258,34 @@ impl<'a> Parser<'a> {
         }
 
         while precedence as usize <= self.get_rule(self.current.kind).precedence as usize {
+            // Any operator that binds less tightly than a call terminates the optional chain, so
+            // any pending short-circuit jumps need to land before we compile it.
+            if (self.get_rule(self.current.kind).precedence as usize) < Precedence::Call as usize {
+                self.end_optional_chain(chain_start);
+            }
             self.advance();
             let infix_rule = self.get_rule(self.previous.kind).infix;
-            infix_rule.unwrap()(self, can_assign);
+            // Assigning through an optional chain isn't supported, since there'd be nothing to
+            // assign to if the chain short-circuits.
+            let in_chain = self.optional_chain_jumps.len() > chain_start;
+            self.comparison = None;
+            infix_rule.unwrap()(self, can_assign && !in_chain);
         }
 
+        self.end_optional_chain(chain_start);
+
         if can_assign && self.match_token(TokenKind::Equal) {
             self.error("Invalid assignment target.");
         }
     }
 
+    fn end_optional_chain(&mut self, chain_start: usize) {
+        while self.optional_chain_jumps.len() > chain_start {
+            let jump = self.optional_chain_jumps.pop().unwrap();
+            self.patch_jump(jump);
+        }
+    }
+
     fn identifier_constant(&mut self, token: &Token) -> u16 {
         let value = Value::ObjString(self.vm.new_gc_obj_string(&token.source));
         self.make_constant(value)
@@ -987,6 +1411,7 @@ impl<'a> Parser<'a> {
 
         match token.kind {
             TokenKind::Eof => write!(error_string, " at end").unwrap(),
+            TokenKind::Newline => write!(error_string, " at newline").unwrap(),
             TokenKind::Error => {}
             _ => write!(error_string, " at '{}'", token.source).unwrap(),
         };
@@ -1064,15 +1489,15 @@ impl<'a> Parser<'a> {
     }
 
     fn resolve_variable(&mut self, name: &Token) -> (OpCode, OpCode, u16) {
-        if let Some(result) = self.resolve_local(&name) {
+        if let Some(result) = self.resolve_local(name) {
             (OpCode::GetLocal, OpCode::SetLocal, result as u16)
-        } else if let Some(result) = self.resolve_upvalue(&name) {
+        } else if let Some(result) = self.resolve_upvalue(name) {
             (OpCode::GetUpvalue, OpCode::SetUpvalue, result as u16)
         } else {
             (
                 OpCode::GetGlobal,
                 OpCode::SetGlobal,
-                self.identifier_constant(&name),
+                self.identifier_constant(name),
             )
         }
     }
@@ -1087,7 +1512,7 @@ impl<'a> Parser<'a> {
             self.binary_assign(get_op, arg);
             self.emit_variable_op(set_op, arg);
         } else {
-            if get_op.arg_sizes() == &[1] {
+            if get_op.arg_sizes() == [1] {
                 self.emit_bytes([get_op as u8, arg as u8]);
             } else {
                 self.emit_constant_op(get_op, arg);
@@ -1096,7 +1521,7 @@ impl<'a> Parser<'a> {
     }
 
     fn compiler(&mut self) -> &Compiler {
-        &self.compilers.last().unwrap()
+        self.compilers.last().unwrap()
     }
 
     fn compiler_mut(&mut self) -> &mut Compiler {
@@ -1145,6 +1570,20 @@ impl<'a> Parser<'a> {
         let rule_precedence = s.get_rule(operator_kind).precedence;
         s.parse_precedence(Precedence::from(rule_precedence as usize + 1));
 
+        let comparison = match operator_kind {
+            TokenKind::BangEqual => Some("!="),
+            TokenKind::EqualEqual => Some("=="),
+            TokenKind::Greater => Some(">"),
+            TokenKind::GreaterEqual => Some(">="),
+            TokenKind::Less => Some("<"),
+            TokenKind::LessEqual => Some("<="),
+            _ => None,
+        };
+        if let Some(op) = comparison {
+            let op_offset = s.chunk().code.len();
+            s.record_comparison(op_offset, op);
+        }
+
         match operator_kind {
             TokenKind::BangEqual => s.emit_bytes([OpCode::Equal as u8, OpCode::Not as u8]),
             TokenKind::EqualEqual => s.emit_byte(OpCode::Equal as u8),
@@ -1160,6 +1599,27 @@ impl<'a> Parser<'a> {
         }
     }
 
+    fn record_comparison(&mut self, op_offset: usize, op: &'static str) {
+        if self.expr_depth == 1 {
+            self.comparison = Some((op_offset, op));
+        }
+    }
+
+    fn membership(s: &mut Parser, _can_assign: bool) {
+        let negated = s.previous.kind == TokenKind::Not;
+        if negated {
+            s.consume(TokenKind::In, "Expected 'in' after 'not'.");
+        }
+        s.parse_precedence(Precedence::from(Precedence::Comparison as usize + 1));
+
+        let op_offset = s.chunk().code.len();
+        s.record_comparison(op_offset, if negated { "not in" } else { "in" });
+        s.emit_byte(OpCode::Contains as u8);
+        if negated {
+            s.emit_byte(OpCode::Not as u8);
+        }
+    }
+
     fn call(s: &mut Parser, _can_assign: bool) {
         let arg_count = s.argument_list(
             TokenKind::RightParen,
@@ -1170,7 +1630,12 @@ impl<'a> Parser<'a> {
     }
 
     fn dot(s: &mut Parser, can_assign: bool) {
-        s.consume(TokenKind::Identifier, "Expected property name after '.'.");
+        let msg = if s.previous.kind == TokenKind::QuestionDot {
+            "Expected property name after '?.'."
+        } else {
+            "Expected property name after '.'."
+        };
+        s.consume(TokenKind::Identifier, msg);
         let previous = s.previous.clone();
         let name = s.identifier_constant(&previous);
 
@@ -1194,9 +1659,67 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn dotdot(s: &mut Parser, _can_assign: bool) {
-        s.parse_precedence(Precedence::Unary);
-        s.emit_byte(OpCode::BuildRange as u8);
+    fn question_dot(s: &mut Parser, _can_assign: bool) {
+        let jump = s.emit_jump(OpCode::JumpIfNil);
+        s.optional_chain_jumps.push(jump);
+        Parser::dot(s, false);
+    }
+
+    fn question_index(s: &mut Parser, _can_assign: bool) {
+        let jump = s.emit_jump(OpCode::JumpIfNil);
+        s.optional_chain_jumps.push(jump);
+        Parser::index(s, false);
+    }
+
+    fn open_range(s: &mut Parser, _can_assign: bool) {
+        s.range_tail(0);
+    }
+
+    fn range(s: &mut Parser, _can_assign: bool) {
+        s.range_tail(chunk::RANGE_HAS_BEGIN);
+    }
+
+    fn range_tail(&mut self, mut flags: u8) {
+        if self.previous.kind == TokenKind::DotDotEqual {
+            flags |= chunk::RANGE_INCLUSIVE;
+        }
+        if !self.range_end_is_open() {
+            self.parse_precedence(Precedence::Unary);
+            flags |= chunk::RANGE_HAS_END;
+        } else if flags & chunk::RANGE_INCLUSIVE != 0 {
+            self.error_at_current("Expected range end after '..='.");
+        }
+        if self.check_step() {
+            self.advance();
+            self.parse_precedence(Precedence::Unary);
+            flags |= chunk::RANGE_HAS_STEP;
+        }
+        self.emit_bytes([OpCode::BuildRange as u8, flags]);
+    }
+
+    fn range_end_is_open(&self) -> bool {
+        self.check_step()
+            || self.check_any(&[
+                TokenKind::RightBracket,
+                TokenKind::RightParen,
+                TokenKind::LeftBrace,
+                TokenKind::RightBrace,
+                TokenKind::Comma,
+                TokenKind::SemiColon,
+                TokenKind::Newline,
+                TokenKind::Eof,
+            ])
+    }
+
+    // The metaclass of a class is a contextual keyword, so it's still available as a variable
+    // name.
+    fn check_metaclass(&self) -> bool {
+        self.check(TokenKind::Identifier) && self.current.source == "metaclass"
+    }
+
+    // The step of a range is a contextual keyword, so it's still available as a variable name.
+    fn check_step(&self) -> bool {
+        self.check(TokenKind::Identifier) && self.current.source == "step"
     }
 
     fn index(s: &mut Parser, can_assign: bool) {
@@ -1237,14 +1760,7 @@ impl<'a> Parser<'a> {
         }
 
         let (function, upvalues) = s.finalise_compiler();
-
-        let constant = s.make_constant(value::Value::ObjFunction(function.as_gc()));
-        s.emit_constant_op(OpCode::Closure, constant);
-
-        for upvalue in upvalues.iter() {
-            s.emit_byte(upvalue.is_local as u8);
-            s.emit_byte(upvalue.index as u8);
-        }
+        s.emit_closure(function, upvalues);
     }
 
     fn hash_map(s: &mut Parser, _can_assign: bool) {
@@ -1277,7 +1793,7 @@ impl<'a> Parser<'a> {
             "Expected ']' after elements.",
         );
 
-        s.emit_bytes([OpCode::BuildVec as u8, num_elems as u8]);
+        s.emit_bytes([OpCode::BuildVec as u8, num_elems]);
     }
 
     fn unary(s: &mut Parser, _can_assign: bool) {
@@ -1386,7 +1902,11 @@ impl<'a> Parser<'a> {
         let previous = s.previous.clone();
         let name = s.identifier_constant(&previous);
 
-        let instance_local_name = s.compiler().locals[0].name.clone();
+        let instance_local_name = if s.compiler().kind == FunctionKind::UnboundMethod {
+            String::from("self")
+        } else {
+            s.compiler().locals[0].name.clone()
+        };
         s.named_variable(Token::from_string(instance_local_name.as_str()), false);
         if s.match_token(TokenKind::LeftParen) {
             let arg_count = s.argument_list(
@@ -1412,6 +1932,17 @@ impl<'a> Parser<'a> {
         s.patch_jump(end_jump);
     }
 
+    fn nil_coalesce(s: &mut Parser, _can_assign: bool) {
+        let else_jump = s.emit_jump(OpCode::JumpIfNil);
+        let end_jump = s.emit_jump(OpCode::Jump);
+
+        s.patch_jump(else_jump);
+        s.emit_byte(OpCode::Pop as u8);
+
+        s.parse_precedence(Precedence::NilCoalesce);
+        s.patch_jump(end_jump);
+    }
+
     fn or(s: &mut Parser, _can_assign: bool) {
         let else_jump = s.emit_jump(OpCode::JumpIfFalse);
         let end_jump = s.emit_jump(OpCode::Jump);
@@ -1424,7 +1955,7 @@ impl<'a> Parser<'a> {
     }
 }
 
-const RULES: [ParseRule; 54] = [
+const RULES: [ParseRule; 64] = [
     // LeftParen
     ParseRule {
         prefix: Some(Parser::grouping),
@@ -1475,8 +2006,14 @@ const RULES: [ParseRule; 54] = [
     },
     // DotDot
     ParseRule {
-        prefix: None,
-        infix: Some(Parser::dotdot),
+        prefix: Some(Parser::open_range),
+        infix: Some(Parser::range),
+        precedence: Precedence::Range,
+    },
+    // DotDotEqual
+    ParseRule {
+        prefix: Some(Parser::open_range),
+        infix: Some(Parser::range),
         precedence: Precedence::Range,
     },
     // Minus
@@ -1515,6 +2052,12 @@ const RULES: [ParseRule; 54] = [
         infix: None,
         precedence: Precedence::None,
     },
+    // Newline
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
     // Slash
     ParseRule {
         prefix: None,
@@ -1593,6 +2136,30 @@ const RULES: [ParseRule; 54] = [
         infix: None,
         precedence: Precedence::None,
     },
+    // QuestionDot
+    ParseRule {
+        prefix: None,
+        infix: Some(Parser::question_dot),
+        precedence: Precedence::Call,
+    },
+    // QuestionLeftBracket
+    ParseRule {
+        prefix: None,
+        infix: Some(Parser::question_index),
+        precedence: Precedence::Call,
+    },
+    // QuestionQuestion
+    ParseRule {
+        prefix: None,
+        infix: Some(Parser::nil_coalesce),
+        precedence: Precedence::NilCoalesce,
+    },
+    // At
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
     // Identifier
     ParseRule {
         prefix: Some(Parser::variable),
@@ -1623,6 +2190,12 @@ const RULES: [ParseRule; 54] = [
         infix: Some(Parser::and),
         precedence: Precedence::And,
     },
+    // Assert
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
     // CapSelf
     ParseRule {
         prefix: Some(Parser::cap_self),
@@ -1631,7 +2204,7 @@ const RULES: [ParseRule; 54] = [
     },
     // Class
     ParseRule {
-        prefix: None,
+        prefix: Some(Parser::class_expression),
         infix: None,
         precedence: Precedence::None,
     },
@@ -1659,6 +2232,12 @@ const RULES: [ParseRule; 54] = [
         infix: None,
         precedence: Precedence::None,
     },
+    // From
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
     // If
     ParseRule {
         prefix: None,
@@ -1680,8 +2259,8 @@ const RULES: [ParseRule; 54] = [
     // In
     ParseRule {
         prefix: None,
-        infix: None,
-        precedence: Precedence::None,
+        infix: Some(Parser::membership),
+        precedence: Precedence::Comparison,
     },
     // Nil
     ParseRule {
@@ -1689,6 +2268,12 @@ const RULES: [ParseRule; 54] = [
         infix: None,
         precedence: Precedence::None,
     },
+    // Not
+    ParseRule {
+        prefix: None,
+        infix: Some(Parser::membership),
+        precedence: Precedence::Comparison,
+    },
     // Or
     ParseRule {
         prefix: None,
@@ -1737,6 +2322,12 @@ const RULES: [ParseRule; 54] = [
         infix: None,
         precedence: Precedence::None,
     },
+    // With
+    ParseRule {
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
     // Error
     ParseRule {
         prefix: None,
@@ -0,0 +1,366 @@
+/* Copyright 2020-2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::core;
+use crate::error::{Error, ErrorKind};
+use crate::object::NativeClosure;
+use crate::value::Value;
+use crate::vm::Vm;
+
+/// Converts a script value to a Rust value, failing if the value has the wrong type.
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Result<Self, Error>;
+}
+
+/// Converts a Rust value to a script value, allocating any objects required to represent it.
+pub trait IntoValue {
+    fn into_value(self, vm: &mut Vm) -> Value;
+}
+
+/// A Rust value that converts to a hashable script value, and so may be used as a key when
+/// converting a `HashMap`.
+pub trait IntoHashKey: IntoValue {}
+
+/// An argument to a native function created with `IntoNative`. Unlike `FromValue`, arguments may
+/// borrow from the values passed to the native function.
+pub trait FromArg {
+    type Output<'a>;
+
+    fn from_arg(value: &Value) -> Result<Self::Output<'_>, Error>;
+}
+
+impl<T: FromValue> FromArg for T {
+    type Output<'a> = T;
+
+    fn from_arg(value: &Value) -> Result<T, Error> {
+        T::from_value(value)
+    }
+}
+
+impl FromArg for &str {
+    type Output<'a> = &'a str;
+
+    fn from_arg(value: &Value) -> Result<&str, Error> {
+        match value {
+            Value::ObjString(string) => Ok(string.as_str()),
+            _ => Err(type_error("a string", value)),
+        }
+    }
+}
+
+fn type_error(expected: &str, value: &Value) -> Error {
+    error!(
+        ErrorKind::TypeError,
+        "Expected {} but found '{}'.", expected, value
+    )
+}
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Result<Self, Error> {
+        Ok(*value)
+    }
+}
+
+impl IntoValue for Value {
+    fn into_value(self, _vm: &mut Vm) -> Value {
+        self
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self, _vm: &mut Vm) -> Value {
+        Value::None
+    }
+}
+
+impl IntoHashKey for () {}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Result<Self, Error> {
+        value
+            .try_as_bool()
+            .ok_or_else(|| type_error("a boolean", value))
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self, _vm: &mut Vm) -> Value {
+        Value::Boolean(self)
+    }
+}
+
+impl IntoHashKey for bool {}
+
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Result<Self, Error> {
+        value
+            .try_as_number()
+            .ok_or_else(|| type_error("a number", value))
+    }
+}
+
+impl IntoValue for f64 {
+    fn into_value(self, _vm: &mut Vm) -> Value {
+        Value::Number(self)
+    }
+}
+
+impl IntoHashKey for f64 {}
+
+impl FromValue for f32 {
+    fn from_value(value: &Value) -> Result<Self, Error> {
+        f64::from_value(value).map(|n| n as f32)
+    }
+}
+
+impl IntoValue for f32 {
+    fn into_value(self, _vm: &mut Vm) -> Value {
+        Value::Number(self as f64)
+    }
+}
+
+impl IntoHashKey for f32 {}
+
+macro_rules! impl_integer_conversions {
+    ($($type:ty),*) => {
+        $(
+            impl FromValue for $type {
+                fn from_value(value: &Value) -> Result<Self, Error> {
+                    let number = f64::from_value(value)?;
+                    // MAX rounds up to MAX + 1 when converted to f64 for 64-bit types, so the
+                    // upper bound is the exactly representable MAX + 1.
+                    let upper = (<$type>::MAX / 2 + 1) as f64 * 2.0;
+                    if number.fract() != 0.0 || number < <$type>::MIN as f64 || number >= upper {
+                        return Err(error!(
+                            ErrorKind::ValueError,
+                            "Expected an integer representable as {} but found '{}'.",
+                            stringify!($type),
+                            value
+                        ));
+                    }
+                    Ok(number as $type)
+                }
+            }
+
+            impl IntoValue for $type {
+                fn into_value(self, _vm: &mut Vm) -> Value {
+                    Value::Number(self as f64)
+                }
+            }
+
+            impl IntoHashKey for $type {}
+        )*
+    };
+}
+
+impl_integer_conversions!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Result<Self, Error> {
+        <&str>::from_arg(value).map(String::from)
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self, vm: &mut Vm) -> Value {
+        self.as_str().into_value(vm)
+    }
+}
+
+impl IntoHashKey for String {}
+
+impl IntoValue for &str {
+    fn into_value(self, vm: &mut Vm) -> Value {
+        Value::ObjString(vm.new_gc_obj_string(self))
+    }
+}
+
+impl IntoHashKey for &str {}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Result<Self, Error> {
+        match value {
+            Value::None => Ok(None),
+            _ => T::from_value(value).map(Some),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self, vm: &mut Vm) -> Value {
+        self.map_or(Value::None, |value| value.into_value(vm))
+    }
+}
+
+impl<T: IntoHashKey> IntoHashKey for Option<T> {}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &Value) -> Result<Self, Error> {
+        let vec = value
+            .try_as_obj_vec()
+            .ok_or_else(|| type_error("a Vec instance", value))?;
+        let vec = vec.borrow();
+        vec.elements.iter().map(T::from_value).collect()
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self, vm: &mut Vm) -> Value {
+        let vec = vm.new_root_obj_vec();
+        for element in self {
+            let element = element.into_value(vm);
+            vec.borrow_mut().elements.push(element);
+        }
+        Value::ObjVec(vec.as_gc())
+    }
+}
+
+impl<K, V> FromValue for HashMap<K, V>
+where
+    K: FromValue + Eq + Hash,
+    V: FromValue,
+{
+    fn from_value(value: &Value) -> Result<Self, Error> {
+        let map = value
+            .try_as_obj_hash_map()
+            .ok_or_else(|| type_error("a HashMap instance", value))?;
+        let map = map.borrow();
+        map.elements
+            .iter()
+            .map(|(key, value)| Ok((K::from_value(key)?, V::from_value(value)?)))
+            .collect()
+    }
+}
+
+impl<K: IntoHashKey, V: IntoValue> IntoValue for HashMap<K, V> {
+    fn into_value(self, vm: &mut Vm) -> Value {
+        let map = vm.new_root_obj_hash_map();
+        // Holds each key while its value is converted, as the conversion may trigger a garbage
+        // collection.
+        let pending = vm.new_root_obj_vec();
+        for (key, value) in self {
+            let key = key.into_value(vm);
+            pending.borrow_mut().elements.push(key);
+            let value = value.into_value(vm);
+            pending.borrow_mut().elements.clear();
+            map.borrow_mut().elements.insert(key, value);
+        }
+        Value::ObjHashMap(map.as_gc())
+    }
+}
+
+macro_rules! count {
+    () => { 0 };
+    ($head:ident $($tail:ident)*) => { 1 + count!($($tail)*) };
+}
+
+macro_rules! impl_tuple_conversions {
+    ($($type:ident),+) => {
+        impl<$($type: FromValue),+> FromValue for ($($type,)+) {
+            fn from_value(value: &Value) -> Result<Self, Error> {
+                let tuple = value
+                    .try_as_obj_tuple()
+                    .ok_or_else(|| type_error("a Tuple instance", value))?;
+                if tuple.elements.len() != count!($($type)+) {
+                    return Err(error!(
+                        ErrorKind::ValueError,
+                        "Expected a Tuple of length {} but found '{}'.",
+                        count!($($type)+),
+                        value
+                    ));
+                }
+                let mut elements = tuple.elements.iter();
+                Ok(($($type::from_value(elements.next().unwrap())?,)+))
+            }
+        }
+
+        impl<$($type: IntoValue),+> IntoValue for ($($type,)+) {
+            #[allow(non_snake_case)]
+            fn into_value(self, vm: &mut Vm) -> Value {
+                let ($($type,)+) = self;
+                // The converted elements are held in a Vec until the tuple is allocated, as each
+                // conversion may trigger a garbage collection.
+                let pending = vm.new_root_obj_vec();
+                $(
+                    let element = $type.into_value(vm);
+                    pending.borrow_mut().elements.push(element);
+                )+
+                let elements = pending.borrow().elements.clone();
+                let tuple = vm.new_root_obj_tuple(elements);
+                Value::ObjTuple(tuple.as_gc())
+            }
+        }
+
+        impl<$($type: IntoHashKey),+> IntoHashKey for ($($type,)+) {}
+    };
+}
+
+impl_tuple_conversions!(A);
+impl_tuple_conversions!(A, B);
+impl_tuple_conversions!(A, B, C);
+impl_tuple_conversions!(A, B, C, D);
+impl_tuple_conversions!(A, B, C, D, E);
+impl_tuple_conversions!(A, B, C, D, E, F);
+
+/// A Rust function that can be wrapped as a native function, with its arguments and return value
+/// converted automatically. Calls with the wrong number or type of arguments raise an error.
+pub trait IntoNative<Args> {
+    fn into_native(self) -> Box<NativeClosure>;
+}
+
+macro_rules! impl_into_native {
+    ($($arg:ident),*) => {
+        impl<Func, Ret, $($arg),*> IntoNative<($($arg,)*)> for Func
+        where
+            Func: Fn($($arg),*) -> Result<Ret, Error>
+                + for<'a> Fn($(<$arg as FromArg>::Output<'a>),*) -> Result<Ret, Error>
+                + 'static,
+            Ret: IntoValue,
+            $($arg: FromArg,)*
+        {
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            fn into_native(self) -> Box<NativeClosure> {
+                // Calling through a function with a single `Fn` bound avoids ambiguity between
+                // the two bounds above.
+                fn call<Ret, $($arg),*>(
+                    function: &impl Fn($($arg),*) -> Result<Ret, Error>,
+                    $($arg: $arg),*
+                ) -> Result<Ret, Error> {
+                    function($($arg),*)
+                }
+
+                Box::new(move |vm: &mut Vm, num_args: usize| {
+                    core::check_num_args(num_args, count!($($arg)*))?;
+                    let args: Vec<Value> = (0..num_args).rev().map(|i| *vm.peek(i)).collect();
+                    let mut args = args.iter();
+                    $(let $arg = $arg::from_arg(args.next().unwrap())?;)*
+                    let result = call(&self, $($arg),*)?;
+                    Ok(result.into_value(vm))
+                })
+            }
+        }
+    };
+}
+
+impl_into_native!();
+impl_into_native!(A);
+impl_into_native!(A, B);
+impl_into_native!(A, B, C);
+impl_into_native!(A, B, C, D);
+impl_into_native!(A, B, C, D, E);
+impl_into_native!(A, B, C, D, E, F);
@@ -14,6 +14,7 @@
  */
 
 use std::char;
+use std::io;
 use std::time;
 
 use crate::common;
@@ -24,7 +25,7 @@ use crate::utils;
 use crate::value::Value;
 use crate::vm::Vm;
 
-fn check_num_args(num_args: usize, expected: usize) -> Result<(), Error> {
+pub(crate) fn check_num_args(num_args: usize, expected: usize) -> Result<(), Error> {
     if num_args != expected {
         return Err(error!(
             ErrorKind::RuntimeError,
@@ -37,7 +38,7 @@ fn check_num_args(num_args: usize, expected: usize) -> Result<(), Error> {
     Ok(())
 }
 
-fn build_methods(
+pub(crate) fn build_methods(
     vm: &mut Vm,
     definitions: &[(&str, NativeFn)],
     extra_methods: Option<ObjStringValueMap>,
@@ -55,7 +56,7 @@ fn build_methods(
     (methods, roots)
 }
 
-/// Global functions
+// Global functions
 
 pub(crate) fn clock(_vm: &mut Vm, _num_args: usize) -> Result<Value, Error> {
     let duration = match time::SystemTime::now().duration_since(time::SystemTime::UNIX_EPOCH) {
@@ -74,16 +75,125 @@ pub(crate) fn clock(_vm: &mut Vm, _num_args: usize) -> Result<Value, Error> {
 
 pub(crate) fn print(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     check_num_args(num_args, 1)?;
-    println!("{}", vm.peek(0));
+    let value = *vm.peek(0);
+    writeln!(vm.output(), "{}", value).map_err(write_error)?;
+    Ok(Value::None)
+}
+
+pub(crate) fn eprint(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+    let value = *vm.peek(0);
+    writeln!(vm.error_output(), "{}", value).map_err(write_error)?;
     Ok(Value::None)
 }
 
+fn write_error(error: io::Error) -> Error {
+    error!(
+        ErrorKind::RuntimeError,
+        "Unable to write output: {}.", error
+    )
+}
+
 pub(crate) fn type_(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     check_num_args(num_args, 1)?;
 
     Ok(Value::ObjClass(vm.get_class(*vm.peek(0))))
 }
 
+pub(crate) fn help(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+
+    let value = match *vm.peek(0) {
+        Value::ObjInstance(instance) => Value::ObjClass(instance.borrow().class),
+        value => value,
+    };
+    let mut text = match value {
+        Value::ObjFunction(function) => format!("{}", *function),
+        Value::ObjClosure(closure) => format!("{}", *closure.borrow().function),
+        Value::ObjBoundMethod(bound) => format!("{}", *bound.borrow().method.borrow().function),
+        Value::ObjClass(class) => format!("class {}", *class.name),
+        value => format!("{}", value),
+    };
+    write_doc(&mut text, value.get_doc(), "    ");
+
+    if let Value::ObjClass(class) = value {
+        let mut methods = class
+            .methods
+            .iter()
+            .filter_map(|(name, method)| method.try_as_obj_closure().map(|c| (*name, c)))
+            .collect::<Vec<_>>();
+        methods.sort_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
+        for (name, closure) in methods {
+            text.push_str(&format!("\n    fn {}", *name));
+            if let Some(doc) = closure.borrow().function.doc {
+                write_doc(&mut text, Some(doc), "        ");
+            }
+        }
+    }
+
+    writeln!(vm.output(), "{}", text).map_err(write_error)?;
+    Ok(Value::None)
+}
+
+fn write_doc(text: &mut String, doc: Option<Gc<ObjString>>, indent: &str) {
+    match doc {
+        Some(doc) => {
+            for line in doc.as_str().lines() {
+                text.push('\n');
+                if !line.is_empty() {
+                    text.push_str(indent);
+                    text.push_str(line);
+                }
+            }
+        }
+        None => {
+            text.push('\n');
+            text.push_str(indent);
+            text.push_str("No documentation.");
+        }
+    }
+}
+
+pub(crate) fn import_module(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+
+    let path = match vm.peek(0).try_as_obj_string() {
+        Some(path) => path,
+        None => {
+            return Err(error!(
+                ErrorKind::TypeError,
+                "Expected a string module path but found '{}'.",
+                vm.peek(0)
+            ));
+        }
+    };
+    if path.as_str() == "main" {
+        return Err(error!(
+            ErrorKind::ValueError,
+            "Cannot import top-level module."
+        ));
+    }
+
+    Ok(Value::ObjModule(vm.import_module(path)?))
+}
+
+pub(crate) fn reload(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+
+    let module = match vm.peek(0).try_as_obj_module() {
+        Some(module) => module,
+        None => {
+            return Err(error!(
+                ErrorKind::TypeError,
+                "Expected a module but found '{}'.",
+                vm.peek(0)
+            ));
+        }
+    };
+    vm.reload(module)?;
+    Ok(Value::ObjModule(module))
+}
+
 pub(crate) fn no_init(vm: &mut Vm, _num_args: usize) -> Result<Value, Error> {
     let class = type_(vm, 1)?;
     Err(error!(
@@ -104,7 +214,7 @@ pub(crate) fn sentinel(_vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     Ok(Value::Sentinel)
 }
 
-/// Type implementation
+// Type implementation
 
 pub(crate) unsafe fn bind_type_class(_vm: &mut Vm, class: &mut GcBoxPtr<ObjClass>) {
     let methods = class
@@ -117,7 +227,7 @@ pub(crate) unsafe fn bind_type_class(_vm: &mut Vm, class: &mut GcBoxPtr<ObjClass
     class.as_mut().data.methods = methods;
 }
 
-/// Object implementation
+// Object implementation
 
 pub(crate) fn object_is_a(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     check_num_args(num_args, 1)?;
@@ -150,7 +260,7 @@ pub(crate) unsafe fn bind_object_class(vm: &mut Vm, class: &mut GcBoxPtr<ObjClas
     class.as_mut().data.methods = methods;
 }
 
-/// String implementation
+// String implementation
 
 pub(crate) unsafe fn bind_gc_obj_string_class(
     vm: &mut Vm,
@@ -176,6 +286,7 @@ pub(crate) unsafe fn bind_gc_obj_string_class(
     let method_map = [
         ("__init__", string_init as NativeFn),
         ("__getitem__", string_get_item as NativeFn),
+        ("__contains__", string_contains as NativeFn),
         ("iter", string_iter as NativeFn),
         ("len", string_len as NativeFn),
         ("count_chars", string_count_chars as NativeFn),
@@ -213,7 +324,7 @@ fn string_from_ascii(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
                 &format!("Expected a number but found '{}'.", value),
             )
         })?;
-        if num < 0.0 || num > 255.0 || num.trunc() != num {
+        if !(0.0..=255.0).contains(&num) || num.trunc() != num {
             return Err(error!(
                 ErrorKind::ValueError,
                 "Expected a positive integer less than 256 but found '{}'.", num
@@ -230,7 +341,7 @@ fn string_from_ascii(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     let string = vm.new_gc_obj_string(&String::from_utf8(bytes).map_err(|_| {
         Error::with_message(
             ErrorKind::ValueError,
-            &format!("Unable to create a string from byte sequence."),
+            "Unable to create a string from byte sequence.",
         )
     })?);
 
@@ -258,7 +369,7 @@ fn string_from_utf8(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
                     &format!("Expected a number but found '{}'.", v),
                 )
             })?;
-            if num < 0.0 || num > 255.0 || num.trunc() != num {
+            if !(0.0..=255.0).contains(&num) || num.trunc() != num {
                 Err(error!(
                     ErrorKind::ValueError,
                     "Expected a positive integer less than 256 but found '{}'.", num
@@ -353,7 +464,18 @@ fn string_get_item(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
             (begin, end)
         }
         Value::ObjRange(r) => {
-            let (begin, end) = r.get_bounded_range(string_len, "String")?;
+            let range = r.get_bounded_range(string_len, "String")?;
+            if range.step != 1 {
+                let mut new_string = String::new();
+                for i in range.indices() {
+                    check_char_boundary(string, i, "string slice index")?;
+                    let c = string.as_str()[i..].chars().next().expect("Expected char.");
+                    new_string.push(c);
+                }
+                let new_string = vm.new_gc_obj_string(&new_string);
+                return Ok(Value::ObjString(new_string));
+            }
+            let (begin, end) = (range.begin as usize, range.end as usize);
             check_char_boundary(string, begin, "string slice start")?;
             check_char_boundary(string, end, "string slice end")?;
             (begin, end)
@@ -371,6 +493,21 @@ fn string_get_item(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     Ok(Value::ObjString(new_string))
 }
 
+fn string_contains(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+
+    let string = vm.peek(1).try_as_obj_string().expect("Expected ObjString.");
+    let substring = vm.peek(0).try_as_obj_string().ok_or_else(|| {
+        error!(
+            ErrorKind::TypeError,
+            "Expected a string but found '{}'.",
+            vm.peek(0)
+        )
+    })?;
+
+    Ok(Value::Boolean(string.as_str().contains(substring.as_str())))
+}
+
 fn string_iter(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     check_num_args(num_args, 0)?;
 
@@ -543,12 +680,12 @@ fn string_as_num(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     check_num_args(num_args, 0)?;
 
     let string = vm.peek(0).try_as_obj_string().expect("Expected ObjString.");
-    let num = string.parse::<f64>().or_else(|_| {
-        Err(error!(
+    let num = string.parse::<f64>().map_err(|_| {
+        error!(
             ErrorKind::ValueError,
             "Unable to parse number from '{}'.",
             vm.peek(0)
-        ))
+        )
     })?;
 
     Ok(Value::Number(num))
@@ -593,7 +730,7 @@ fn check_char_boundary(string: Gc<ObjString>, pos: usize, desc: &str) -> Result<
     Ok(())
 }
 
-/// StringIter implementation
+// StringIter implementation
 
 fn string_iter_next(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     check_num_args(num_args, 0)?;
@@ -625,7 +762,7 @@ pub fn new_root_obj_string_iter_class(
     object::new_root_obj_class(vm, class_name, metaclass, Some(superclass), methods)
 }
 
-/// Tuple implementation
+// Tuple implementation
 
 pub fn new_root_obj_tuple_class(
     vm: &mut Vm,
@@ -636,6 +773,7 @@ pub fn new_root_obj_tuple_class(
     let method_map = [
         ("__init__", tuple_init as NativeFn),
         ("__getitem__", tuple_get_item as NativeFn),
+        ("__contains__", tuple_contains as NativeFn),
         ("len", tuple_len as NativeFn),
         ("iter", tuple_iter as NativeFn),
     ];
@@ -664,8 +802,12 @@ fn tuple_get_item(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
         }
         Value::ObjRange(r) => {
             let tuple_len = tuple.elements.len() as isize;
-            let (begin, end) = r.get_bounded_range(tuple_len, "Tuple")?;
-            let new_elements = Vec::from(&tuple.elements[begin..end]);
+            let range = r.get_bounded_range(tuple_len, "Tuple")?;
+            let new_elements = if range.step == 1 {
+                Vec::from(&tuple.elements[range.begin as usize..range.end as usize])
+            } else {
+                range.indices().map(|i| tuple.elements[i]).collect()
+            };
             let new_tuple = object::new_gc_obj_tuple(vm, tuple.class, new_elements);
             Ok(Value::ObjTuple(new_tuple))
         }
@@ -676,6 +818,13 @@ fn tuple_get_item(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     }
 }
 
+fn tuple_contains(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+
+    let tuple = vm.peek(1).try_as_obj_tuple().expect("Expected ObjTuple");
+    Ok(Value::Boolean(tuple.elements.contains(vm.peek(0))))
+}
+
 fn tuple_len(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     check_num_args(num_args, 0)?;
 
@@ -694,7 +843,7 @@ fn tuple_iter(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     Ok(Value::ObjTupleIter(iter.as_gc()))
 }
 
-/// TupleIter implementation
+// TupleIter implementation
 
 pub fn new_root_obj_tuple_iter_class(
     vm: &mut Vm,
@@ -717,7 +866,7 @@ fn tuple_iter_next(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     Ok(borrowed_iter.next())
 }
 
-/// Vec implemenation
+// Vec implemenation
 
 pub fn new_root_obj_vec_class(
     vm: &mut Vm,
@@ -731,6 +880,7 @@ pub fn new_root_obj_vec_class(
         ("pop", vec_pop as NativeFn),
         ("__getitem__", vec_get_item as NativeFn),
         ("__setitem__", vec_set_item as NativeFn),
+        ("__contains__", vec_contains as NativeFn),
         ("len", vec_len as NativeFn),
         ("iter", vec_iter as NativeFn),
     ];
@@ -787,12 +937,19 @@ fn vec_get_item(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
         }
         Value::ObjRange(r) => {
             let vec_len = vec.borrow().elements.len() as isize;
-            let (begin, end) = r.get_bounded_range(vec_len, "Vec")?;
+            let range = r.get_bounded_range(vec_len, "Vec")?;
             let new_vec = object::new_gc_obj_vec(vm, vec.borrow().class);
-            new_vec
-                .borrow_mut()
-                .elements
-                .extend_from_slice(&vec.borrow().elements[begin..end]);
+            if range.step == 1 {
+                new_vec.borrow_mut().elements.extend_from_slice(
+                    &vec.borrow().elements[range.begin as usize..range.end as usize],
+                );
+            } else {
+                let borrowed_vec = vec.borrow();
+                new_vec
+                    .borrow_mut()
+                    .elements
+                    .extend(range.indices().map(|i| borrowed_vec.elements[i]));
+            }
             Ok(Value::ObjVec(new_vec))
         }
         _ => Err(error!(
@@ -816,6 +973,14 @@ fn vec_set_item(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     Ok(Value::None)
 }
 
+fn vec_contains(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+
+    let vec = vm.peek(1).try_as_obj_vec().expect("Expected ObjVec");
+    let borrowed_vec = vec.borrow();
+    Ok(Value::Boolean(borrowed_vec.elements.contains(vm.peek(0))))
+}
+
 fn vec_len(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     check_num_args(num_args, 0)?;
 
@@ -847,7 +1012,7 @@ fn get_bounded_index(value: Value, bound: isize, msg: &str) -> Result<usize, Err
     Ok(index as usize)
 }
 
-/// VecIter implementation
+// VecIter implementation
 
 pub fn new_root_obj_vec_iter_class(
     vm: &mut Vm,
@@ -869,7 +1034,7 @@ fn vec_iter_next(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     Ok(borrowed_iter.next())
 }
 
-/// Range implementation
+// Range implementation
 
 pub fn new_root_obj_range_class(
     vm: &mut Vm,
@@ -879,6 +1044,7 @@ pub fn new_root_obj_range_class(
     let class_name = vm.new_gc_obj_string("Range");
     let method_map = [
         ("__init__", range_init as NativeFn),
+        ("__contains__", range_contains as NativeFn),
         ("iter", range_iter as NativeFn),
     ];
     let (methods, _native_roots) = build_methods(vm, &method_map, None);
@@ -894,18 +1060,35 @@ fn range_init(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     Ok(Value::ObjRange(range.as_gc()))
 }
 
+fn range_contains(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    check_num_args(num_args, 1)?;
+
+    let range = vm.peek(1).try_as_obj_range().expect("Expected ObjRange.");
+    let contained = match utils::validate_integer(*vm.peek(0)) {
+        Ok(value) => range.contains(value),
+        Err(_) => false,
+    };
+    Ok(Value::Boolean(contained))
+}
+
 fn range_iter(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     check_num_args(num_args, 0)?;
 
-    let iter = vm.new_root_obj_range_iter(
-        vm.peek(0)
-            .try_as_obj_range()
-            .expect("Expected ObjRange instance."),
-    );
+    let range = vm
+        .peek(0)
+        .try_as_obj_range()
+        .expect("Expected ObjRange instance.");
+    if range.begin.is_none() {
+        return Err(error!(
+            ErrorKind::ValueError,
+            "Cannot iterate over a range without a start."
+        ));
+    }
+    let iter = vm.new_root_obj_range_iter(range);
     Ok(Value::ObjRangeIter(iter.as_gc()))
 }
 
-/// RangeIter implementation
+// RangeIter implementation
 
 fn range_iter_next(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
     check_num_args(num_args, 0)?;
@@ -928,7 +1111,7 @@ pub fn new_root_obj_range_iter_class(
     object::new_root_obj_class(vm, class_name, metaclass, Some(superclass), methods)
 }
 
-/// HashMap implementation
+// HashMap implementation
 
 pub fn new_root_obj_hash_map_class(
     vm: &mut Vm,
@@ -939,6 +1122,7 @@ pub fn new_root_obj_hash_map_class(
     let method_map = [
         ("__init__", hash_map_init as NativeFn),
         ("has_key", hash_map_has_key as NativeFn),
+        ("__contains__", hash_map_has_key as NativeFn),
         ("get", hash_map_get as NativeFn),
         ("insert", hash_map_insert as NativeFn),
         ("remove", hash_map_remove as NativeFn),
@@ -1052,7 +1236,7 @@ fn hash_map_keys(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
         .try_as_obj_hash_map()
         .expect("Expected ObjHashMap");
     let borrowed_hash_map = hash_map.borrow();
-    let keys: Vec<_> = borrowed_hash_map.elements.keys().map(|&v| v).collect();
+    let keys: Vec<_> = borrowed_hash_map.elements.keys().copied().collect();
     let obj_keys = vm.new_root_obj_vec();
     obj_keys.borrow_mut().elements = keys;
     Ok(Value::ObjVec(obj_keys.as_gc()))
@@ -1066,7 +1250,7 @@ fn hash_map_values(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
         .try_as_obj_hash_map()
         .expect("Expected ObjHashMap");
     let borrowed_hash_map = hash_map.borrow();
-    let values: Vec<_> = borrowed_hash_map.elements.values().map(|&v| v).collect();
+    let values: Vec<_> = borrowed_hash_map.elements.values().copied().collect();
     let obj_values = vm.new_root_obj_vec();
     obj_values.borrow_mut().elements = values;
     Ok(Value::ObjVec(obj_values.as_gc()))
@@ -42,6 +42,7 @@ pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> usize {
         OpCode::False => simple_instruction("FALSE", offset),
         OpCode::Pop => simple_instruction("POP", offset),
         OpCode::CopyTop => simple_instruction("COPY_TOP", offset),
+        OpCode::CopyTopTwo => simple_instruction("COPY_TOP_TWO", offset),
         OpCode::GetLocal => byte_instruction("GET_LOCAL", chunk, offset),
         OpCode::SetLocal => byte_instruction("SET_LOCAL", chunk, offset),
         OpCode::GetGlobal => constant_instruction("GET_GLOBAL", chunk, offset),
@@ -56,6 +57,7 @@ pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> usize {
         OpCode::Equal => simple_instruction("EQUAL", offset),
         OpCode::Greater => simple_instruction("GREATER", offset),
         OpCode::Less => simple_instruction("LESS", offset),
+        OpCode::Contains => simple_instruction("CONTAINS", offset),
         OpCode::Add => simple_instruction("ADD", offset),
         OpCode::Subtract => simple_instruction("SUBTRACT", offset),
         OpCode::Multiply => simple_instruction("MULTIPLY", offset),
@@ -63,7 +65,7 @@ pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> usize {
         OpCode::Not => simple_instruction("NOT", offset),
         OpCode::Negate => simple_instruction("NEGATE", offset),
         OpCode::BuildHashMap => byte_instruction("BUILD_HASH_MAP", chunk, offset),
-        OpCode::BuildRange => simple_instruction("BUILD_RANGE", offset),
+        OpCode::BuildRange => byte_instruction("BUILD_RANGE", chunk, offset),
         OpCode::BuildString => byte_instruction("BUILD_STRING", chunk, offset),
         OpCode::BuildTuple => byte_instruction("BUILD_TUPLE", chunk, offset),
         OpCode::FormatString => simple_instruction("FORMAT_STRING", offset),
@@ -71,6 +73,7 @@ pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> usize {
         OpCode::IterNext => simple_instruction("ITER_NEXT", offset),
         OpCode::Jump => jump_instruction("JUMP", 1, chunk, offset),
         OpCode::JumpIfFalse => jump_instruction("JUMP_IF_FALSE", 1, chunk, offset),
+        OpCode::JumpIfNil => jump_instruction("JUMP_IF_NIL", 1, chunk, offset),
         OpCode::JumpIfSentinel => jump_instruction("JUMP_IF_SENTINEL", 1, chunk, offset),
         OpCode::Loop => jump_instruction("LOOP", -1, chunk, offset),
         OpCode::Call => byte_instruction("CALL", chunk, offset),
@@ -113,13 +116,20 @@ pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> usize {
         }
         OpCode::CloseUpvalue => simple_instruction("CLOSE_UPVALUE", offset),
         OpCode::Return => simple_instruction("RETURN", offset),
+        OpCode::EnterWith => byte_instruction("ENTER_WITH", chunk, offset),
+        OpCode::ExitWith => simple_instruction("EXIT_WITH", offset),
+        OpCode::Assert => simple_instruction("ASSERT", offset),
+        OpCode::AssertCompare => constant_instruction("ASSERT_COMPARE", chunk, offset),
         OpCode::DeclareClass => constant_instruction("DECLARE_CLASS", chunk, offset),
         OpCode::DefineClass => simple_instruction("DEFINE_CLASS", offset),
         OpCode::Inherit => simple_instruction("INHERIT", offset),
+        OpCode::Metaclass => simple_instruction("METACLASS", offset),
+        OpCode::ClassDoc => constant_instruction("CLASS_DOC", chunk, offset),
         OpCode::Method => constant_instruction("METHOD", chunk, offset),
         OpCode::StaticMethod => constant_instruction("STATIC_METHOD", chunk, offset),
         OpCode::StartImport => constant_instruction("START_IMPORT", chunk, offset),
         OpCode::FinishImport => simple_instruction("FINISH_IMPORT", offset),
+        OpCode::ImportFrom => constant_instruction("IMPORT_FROM", chunk, offset),
     }
 }
 
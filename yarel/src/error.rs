@@ -17,11 +17,15 @@ use std::fmt;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ErrorKind {
+    AssertionError,
     AttributeError,
     CompileError,
+    FuelError,
     IndexError,
+    InterruptError,
     NameError,
     RuntimeError,
+    TimeoutError,
     TypeError,
     ValueError,
 }
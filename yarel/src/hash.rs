@@ -45,16 +45,11 @@ impl Hasher for FnvHasher {
     }
 }
 
+#[derive(Default)]
 pub struct PassThroughHasher {
     hash: u64,
 }
 
-impl Default for PassThroughHasher {
-    fn default() -> Self {
-        PassThroughHasher { hash: 0 }
-    }
-}
-
 impl Hasher for PassThroughHasher {
     fn write(&mut self, msg: &[u8]) {
         // This is a little contrived, but the hasher should only ever have write_u64 called on it.
@@ -66,15 +61,9 @@ impl Hasher for PassThroughHasher {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct BuildPassThroughHasher;
 
-impl Default for BuildPassThroughHasher {
-    fn default() -> Self {
-        BuildPassThroughHasher {}
-    }
-}
-
 impl BuildHasher for BuildPassThroughHasher {
     type Hasher = PassThroughHasher;
 
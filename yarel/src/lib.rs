@@ -19,13 +19,18 @@ pub mod chunk;
 pub mod class_store;
 mod common;
 pub mod compiler;
+pub mod convert;
 mod core;
 mod debug;
 mod hash;
 pub mod memory;
+pub mod module_loader;
 pub mod object;
 mod scanner;
 mod stack;
+mod unicode;
+#[rustfmt::skip]
+mod unicode_tables;
 mod utils;
 pub mod value;
 pub mod vm;
@@ -88,6 +88,10 @@ pub struct Root<T: 'static + GcManaged + ?Sized> {
 }
 
 impl<T: GcManaged> Root<T> {
+    /// # Safety
+    ///
+    /// The returned root doesn't point to anything. It must be replaced with a valid root before
+    /// it is used.
     pub unsafe fn dangling() -> Self {
         Root { ptr: None }
     }
@@ -301,7 +305,7 @@ impl Heap {
         gc_box_ptr
     }
 
-    fn collect(&mut self, static_roots: &[&dyn GcManaged]) {
+    pub(crate) fn collect(&mut self, static_roots: &[&dyn GcManaged]) {
         if cfg!(feature = "debug_trace_gc") {
             println!("-- gc begin")
         }
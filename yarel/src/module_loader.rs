@@ -0,0 +1,201 @@
+/* Copyright 2020-2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::error::{Error, ErrorKind};
+
+/// Locates and reads the source code of modules imported by scripts.
+pub trait ModuleLoader {
+    /// Resolves the path in an import statement to the path that identifies the imported module.
+    /// `importer` is the path of the importing module, or the path of the index module within a
+    /// package. By default, paths starting with './' or '../' are resolved against the directory
+    /// of the importing module and all other paths are returned unchanged.
+    fn resolve(&self, path: &str, importer: &str) -> Result<String, Error> {
+        Ok(resolve_relative_path(path, importer))
+    }
+
+    /// Returns the source code of the module with the provided resolved path.
+    fn load(&mut self, path: &str) -> Result<String, Error>;
+}
+
+/// Loads modules from files with the '.yl' extension, relative to the current working directory.
+#[derive(Default)]
+pub struct FileSystemLoader;
+
+impl FileSystemLoader {
+    pub fn new() -> Self {
+        FileSystemLoader
+    }
+}
+
+impl ModuleLoader for FileSystemLoader {
+    fn load(&mut self, path: &str) -> Result<String, Error> {
+        let path = Path::new(path).with_extension("yl");
+        let filename = match path.as_path().to_str() {
+            Some(p) => p,
+            None => {
+                return Err(error!(
+                    ErrorKind::RuntimeError,
+                    "Error converting module path to string."
+                ));
+            }
+        };
+
+        let source = match fs::read_to_string(filename) {
+            Ok(s) => s,
+            Err(e) => {
+                let reason = match e.kind() {
+                    io::ErrorKind::NotFound => "file not found",
+                    io::ErrorKind::PermissionDenied => "permission denied",
+                    io::ErrorKind::ConnectionRefused => "connection refused",
+                    io::ErrorKind::ConnectionReset => "connection reset",
+                    io::ErrorKind::ConnectionAborted => "connection aborted",
+                    io::ErrorKind::NotConnected => "not connected",
+                    io::ErrorKind::AddrInUse => "address in use",
+                    io::ErrorKind::AddrNotAvailable => "address not available",
+                    io::ErrorKind::BrokenPipe => "broken pipe",
+                    io::ErrorKind::AlreadyExists => "already exists",
+                    io::ErrorKind::WouldBlock => "would block",
+                    io::ErrorKind::InvalidInput => "invalid input",
+                    io::ErrorKind::InvalidData => "invalid data",
+                    io::ErrorKind::TimedOut => "timed out",
+                    io::ErrorKind::WriteZero => "write zero",
+                    io::ErrorKind::Interrupted => "interrupted",
+                    io::ErrorKind::Other => "other",
+                    io::ErrorKind::UnexpectedEof => "unexpected end-of-file",
+                    _ => "other",
+                };
+                return Err(error!(
+                    ErrorKind::RuntimeError,
+                    "Unable to read file '{}' ({}).", filename, reason
+                ));
+            }
+        };
+
+        Ok(source)
+    }
+}
+
+/// Loads modules from sources held in memory, such as those embedded with `include_str!`.
+#[derive(Default)]
+pub struct MemoryLoader {
+    sources: HashMap<String, String>,
+}
+
+impl MemoryLoader {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds the source of the module with the provided path, replacing any existing source.
+    pub fn insert(&mut self, path: &str, source: &str) {
+        self.sources
+            .insert(String::from(path), String::from(source));
+    }
+}
+
+impl ModuleLoader for MemoryLoader {
+    fn load(&mut self, path: &str) -> Result<String, Error> {
+        self.sources
+            .get(path)
+            .cloned()
+            .ok_or_else(|| error!(ErrorKind::RuntimeError, "Unable to find module '{}'.", path))
+    }
+}
+
+/// Tries each of a sequence of loaders in turn, using the first that succeeds. Import paths are
+/// resolved by the first loader alone, so every loader is asked for the same resolved path.
+#[derive(Default)]
+pub struct ChainedLoader {
+    loaders: Vec<Box<dyn ModuleLoader>>,
+}
+
+impl ChainedLoader {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds a loader to the end of the chain.
+    pub fn with_loader(mut self, loader: impl ModuleLoader + 'static) -> Self {
+        self.loaders.push(Box::new(loader));
+        self
+    }
+}
+
+impl ModuleLoader for ChainedLoader {
+    fn resolve(&self, path: &str, importer: &str) -> Result<String, Error> {
+        match self.loaders.first() {
+            Some(loader) => loader.resolve(path, importer),
+            None => Ok(resolve_relative_path(path, importer)),
+        }
+    }
+
+    fn load(&mut self, path: &str) -> Result<String, Error> {
+        let mut first_error = None;
+        for loader in &mut self.loaders {
+            match loader.load(path) {
+                Ok(source) => return Ok(source),
+                Err(error) => {
+                    first_error.get_or_insert(error);
+                }
+            }
+        }
+        Err(first_error.unwrap_or_else(|| {
+            error!(ErrorKind::RuntimeError, "Unable to find module '{}'.", path)
+        }))
+    }
+}
+
+// Resolves paths starting with './' or '../' against the directory of the importing module,
+// returning all other paths unchanged.
+fn resolve_relative_path(path: &str, importer: &str) -> String {
+    if !path.starts_with("./") && !path.starts_with("../") {
+        return String::from(path);
+    }
+    let directory = importer.rsplit_once('/').map_or("", |(parent, _)| parent);
+    join_module_paths(directory, path)
+}
+
+// Joins two '/'-separated module paths, resolving any '.' and '..' components.
+pub(crate) fn join_module_paths(base: &str, path: &str) -> String {
+    let (absolute, mut components) = if path.starts_with('/') {
+        (true, Vec::new())
+    } else {
+        let components = base
+            .split('/')
+            .filter(|c| !c.is_empty())
+            .collect::<Vec<_>>();
+        (base.starts_with('/'), components)
+    };
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." if components.last().is_some_and(|&c| c != "..") => {
+                components.pop();
+            }
+            _ => components.push(component),
+        }
+    }
+    let joined = components.join("/");
+    if absolute {
+        format!("/{}", joined)
+    } else {
+        joined
+    }
+}
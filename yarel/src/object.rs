@@ -13,12 +13,14 @@
  * limitations under the License.
  */
 
+use std::any::Any;
 use std::cell::{Cell, RefCell};
 use std::cmp::{self, Eq};
 use std::collections::HashMap;
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::ops::Deref;
+use std::ptr;
 
 use crate::error::{Error, ErrorKind};
 use crate::hash::{BuildPassThroughHasher, PassThroughHasher};
@@ -77,7 +79,7 @@ impl memory::GcManaged for ObjString {
 pub type ObjStringValueMap = HashMap<Gc<ObjString>, Value, BuildPassThroughHasher>;
 
 pub fn new_obj_string_value_map() -> ObjStringValueMap {
-    ObjStringValueMap::with_hasher(BuildPassThroughHasher::default())
+    ObjStringValueMap::with_hasher(BuildPassThroughHasher)
 }
 
 pub struct ObjStringIter {
@@ -204,6 +206,7 @@ pub struct ObjFunction {
     pub chunk_index: usize,
     pub name: Gc<ObjString>,
     pub(crate) module_path: Gc<ObjString>,
+    pub doc: Option<Gc<ObjString>>,
 }
 
 pub fn new_gc_obj_function(
@@ -213,6 +216,7 @@ pub fn new_gc_obj_function(
     upvalue_count: usize,
     chunk_index: usize,
     module_path: Gc<ObjString>,
+    doc: Option<Gc<ObjString>>,
 ) -> Gc<ObjFunction> {
     vm.allocate(ObjFunction::new(
         name,
@@ -220,6 +224,7 @@ pub fn new_gc_obj_function(
         upvalue_count,
         chunk_index,
         module_path,
+        doc,
     ))
 }
 
@@ -230,8 +235,18 @@ pub fn new_root_obj_function(
     upvalue_count: usize,
     chunk_index: usize,
     module_path: Gc<ObjString>,
+    doc: Option<Gc<ObjString>>,
 ) -> Root<ObjFunction> {
-    new_gc_obj_function(vm, name, arity, upvalue_count, chunk_index, module_path).as_root()
+    new_gc_obj_function(
+        vm,
+        name,
+        arity,
+        upvalue_count,
+        chunk_index,
+        module_path,
+        doc,
+    )
+    .as_root()
 }
 
 impl ObjFunction {
@@ -241,6 +256,7 @@ impl ObjFunction {
         upvalue_count: usize,
         chunk_index: usize,
         module_path: Gc<ObjString>,
+        doc: Option<Gc<ObjString>>,
     ) -> Self {
         ObjFunction {
             name,
@@ -248,6 +264,7 @@ impl ObjFunction {
             upvalue_count,
             chunk_index,
             module_path,
+            doc,
         }
     }
 }
@@ -255,10 +272,16 @@ impl ObjFunction {
 impl memory::GcManaged for ObjFunction {
     fn mark(&self) {
         self.name.mark();
+        if let Some(doc) = self.doc {
+            doc.mark();
+        }
     }
 
     fn blacken(&self) {
         self.name.blacken();
+        if let Some(doc) = self.doc {
+            doc.blacken();
+        }
     }
 }
 
@@ -273,13 +296,22 @@ impl fmt::Display for ObjFunction {
 
 pub type NativeFn = fn(&mut Vm, usize) -> Result<Value, Error>;
 
+pub type NativeClosure = dyn FnMut(&mut Vm, usize) -> Result<Value, Error>;
+
+/// The Rust code backing a native function. Closures may own host state, but any values they
+/// capture are not traced by the garbage collector and so must be held as roots.
+pub enum NativeFunction {
+    Fn(NativeFn),
+    Closure(RefCell<Box<NativeClosure>>),
+}
+
 pub struct ObjNative {
     pub(crate) name: Gc<ObjString>,
-    pub function: NativeFn,
+    pub function: NativeFunction,
 }
 
 pub fn new_gc_obj_native(vm: &mut Vm, name: Gc<ObjString>, function: NativeFn) -> Gc<ObjNative> {
-    vm.allocate(ObjNative::new(name, function))
+    vm.allocate(ObjNative::new(name, NativeFunction::Fn(function)))
 }
 
 pub fn new_root_obj_native(
@@ -290,8 +322,25 @@ pub fn new_root_obj_native(
     new_gc_obj_native(vm, name, function).as_root()
 }
 
+pub fn new_gc_obj_native_closure(
+    vm: &mut Vm,
+    name: Gc<ObjString>,
+    function: impl FnMut(&mut Vm, usize) -> Result<Value, Error> + 'static,
+) -> Gc<ObjNative> {
+    let function = NativeFunction::Closure(RefCell::new(Box::new(function)));
+    vm.allocate(ObjNative::new(name, function))
+}
+
+pub fn new_root_obj_native_closure(
+    vm: &mut Vm,
+    name: Gc<ObjString>,
+    function: impl FnMut(&mut Vm, usize) -> Result<Value, Error> + 'static,
+) -> Root<ObjNative> {
+    new_gc_obj_native_closure(vm, name, function).as_root()
+}
+
 impl ObjNative {
-    fn new(name: Gc<ObjString>, function: NativeFn) -> Self {
+    fn new(name: Gc<ObjString>, function: NativeFunction) -> Self {
         ObjNative { name, function }
     }
 }
@@ -372,6 +421,7 @@ pub struct ObjClass {
     pub metaclass: Gc<ObjClass>,
     pub superclass: Option<Gc<ObjClass>>,
     pub methods: HashMap<Gc<ObjString>, Value, BuildPassThroughHasher>,
+    pub doc: Option<Gc<ObjString>>,
 }
 
 pub fn new_gc_obj_class(
@@ -380,6 +430,17 @@ pub fn new_gc_obj_class(
     metaclass: Gc<ObjClass>,
     superclass: Option<Gc<ObjClass>>,
     methods: ObjStringValueMap,
+) -> Gc<ObjClass> {
+    new_gc_obj_class_with_doc(vm, name, metaclass, superclass, methods, None)
+}
+
+pub fn new_gc_obj_class_with_doc(
+    vm: &mut Vm,
+    name: Gc<ObjString>,
+    metaclass: Gc<ObjClass>,
+    superclass: Option<Gc<ObjClass>>,
+    methods: ObjStringValueMap,
+    doc: Option<Gc<ObjString>>,
 ) -> Gc<ObjClass> {
     let mut merged_methods = if let Some(parent) = superclass {
         parent.methods.clone()
@@ -389,7 +450,9 @@ pub fn new_gc_obj_class(
     for (&k, &v) in &methods {
         merged_methods.insert(k, v);
     }
-    vm.allocate(ObjClass::new(name, metaclass, superclass, merged_methods))
+    let mut class = ObjClass::new(name, metaclass, superclass, merged_methods);
+    class.doc = doc;
+    vm.allocate(class)
 }
 
 pub fn new_root_obj_class(
@@ -414,6 +477,7 @@ impl ObjClass {
             metaclass,
             superclass,
             methods,
+            doc: None,
         }
     }
 }
@@ -422,11 +486,17 @@ impl memory::GcManaged for ObjClass {
     fn mark(&self) {
         self.metaclass.mark();
         self.methods.mark();
+        if let Some(doc) = self.doc {
+            doc.mark();
+        }
     }
 
     fn blacken(&self) {
         self.metaclass.blacken();
         self.methods.blacken();
+        if let Some(doc) = self.doc {
+            doc.blacken();
+        }
     }
 }
 
@@ -453,7 +523,7 @@ impl ObjInstance {
     fn new(class: Gc<ObjClass>) -> Self {
         ObjInstance {
             class,
-            fields: HashMap::with_hasher(BuildPassThroughHasher::default()),
+            fields: HashMap::with_hasher(BuildPassThroughHasher),
         }
     }
 }
@@ -476,6 +546,95 @@ impl fmt::Display for ObjInstance {
     }
 }
 
+/// Converts a reference to `Any`, so that foreign data can be downcast to its concrete type.
+/// Implemented for all types.
+pub trait AsAny: Any {
+    fn as_any(&self) -> &dyn Any;
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Rust data that can be exposed to scripts as an instance of a script class. Implementations
+/// that hold values must mark and blacken them so that they're traced by the garbage collector.
+pub trait Foreign: AsAny + memory::GcManaged {
+    /// Called when the object is freed by the garbage collector. Any values held by the object may
+    /// already have been freed and so must not be accessed.
+    fn finalise(&mut self) {}
+}
+
+pub struct ObjForeign {
+    pub class: memory::Gc<ObjClass>,
+    data: Box<dyn Foreign>,
+}
+
+pub fn new_gc_obj_foreign(
+    vm: &mut Vm,
+    class: Gc<ObjClass>,
+    data: impl Foreign,
+) -> Gc<RefCell<ObjForeign>> {
+    vm.allocate(RefCell::new(ObjForeign::new(class, Box::new(data))))
+}
+
+pub fn new_root_obj_foreign(
+    vm: &mut Vm,
+    class: Gc<ObjClass>,
+    data: impl Foreign,
+) -> Root<RefCell<ObjForeign>> {
+    new_gc_obj_foreign(vm, class, data).as_root()
+}
+
+impl ObjForeign {
+    fn new(class: Gc<ObjClass>, data: Box<dyn Foreign>) -> Self {
+        ObjForeign { class, data }
+    }
+
+    pub fn is<T: Foreign>(&self) -> bool {
+        self.downcast_ref::<T>().is_some()
+    }
+
+    pub fn downcast_ref<T: Foreign>(&self) -> Option<&T> {
+        (*self.data).as_any().downcast_ref()
+    }
+
+    pub fn downcast_mut<T: Foreign>(&mut self) -> Option<&mut T> {
+        (*self.data).as_any_mut().downcast_mut()
+    }
+}
+
+impl Drop for ObjForeign {
+    fn drop(&mut self) {
+        self.data.finalise();
+    }
+}
+
+impl memory::GcManaged for ObjForeign {
+    fn mark(&self) {
+        self.class.mark();
+        self.data.mark();
+    }
+
+    fn blacken(&self) {
+        self.class.blacken();
+        self.data.blacken();
+    }
+}
+
+impl fmt::Display for ObjForeign {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} instance", *self.class)
+    }
+}
+
 pub struct ObjBoundMethod<T: memory::GcManaged> {
     pub receiver: Value,
     pub method: memory::Gc<T>,
@@ -590,7 +749,7 @@ impl fmt::Display for ObjVec {
 
 impl cmp::PartialEq for ObjVec {
     fn eq(&self, other: &ObjVec) -> bool {
-        if self as *const _ == other as *const _ {
+        if ptr::eq(self, other) {
             return true;
         }
         self.elements == other.elements
@@ -657,64 +816,166 @@ impl fmt::Display for ObjVecIter {
 
 pub struct ObjRange {
     pub class: Gc<ObjClass>,
-    pub begin: isize,
-    pub end: isize,
+    pub begin: Option<isize>,
+    pub end: Option<isize>,
+    pub step: Option<isize>,
+    pub inclusive: bool,
 }
 
 pub fn new_gc_obj_range(
     vm: &mut Vm,
     class: Gc<ObjClass>,
-    begin: isize,
-    end: isize,
+    begin: Option<isize>,
+    end: Option<isize>,
+    step: Option<isize>,
+    inclusive: bool,
 ) -> Gc<ObjRange> {
-    vm.allocate(ObjRange::new(class, begin, end))
+    vm.allocate(ObjRange::new(class, begin, end, step, inclusive))
 }
 
 pub fn new_root_obj_range(
     vm: &mut Vm,
     class: Gc<ObjClass>,
-    begin: isize,
-    end: isize,
+    begin: Option<isize>,
+    end: Option<isize>,
+    step: Option<isize>,
+    inclusive: bool,
 ) -> Root<ObjRange> {
-    new_gc_obj_range(vm, class, begin, end).as_root()
+    new_gc_obj_range(vm, class, begin, end, step, inclusive).as_root()
+}
+
+/// The indices selected by slicing a container with a range, resolved against the length of
+/// the container. The end index is exclusive.
+pub(crate) struct BoundedRange {
+    pub(crate) begin: isize,
+    pub(crate) end: isize,
+    pub(crate) step: isize,
+}
+
+impl BoundedRange {
+    pub(crate) fn indices(&self) -> impl Iterator<Item = usize> {
+        let (begin, end, step) = (self.begin, self.end, self.step);
+        // Iteration ends early if stepping past the current index would overflow.
+        let mut current = Some(begin);
+        std::iter::from_fn(move || {
+            let index = current?;
+            if (step > 0 && index >= end) || (step < 0 && index <= end) {
+                return None;
+            }
+            current = index.checked_add(step);
+            Some(index as usize)
+        })
+    }
 }
 
 impl ObjRange {
-    fn new(class: Gc<ObjClass>, begin: isize, end: isize) -> Self {
-        ObjRange { class, begin, end }
+    fn new(
+        class: Gc<ObjClass>,
+        begin: Option<isize>,
+        end: Option<isize>,
+        step: Option<isize>,
+        inclusive: bool,
+    ) -> Self {
+        ObjRange {
+            class,
+            begin,
+            end,
+            step,
+            inclusive,
+        }
+    }
+
+    /// The step used when iterating over the range. Ranges without an explicit step count
+    /// towards their end.
+    pub(crate) fn iter_step(&self) -> isize {
+        self.step.unwrap_or(match (self.begin, self.end) {
+            (Some(begin), Some(end)) if begin > end => -1,
+            _ => 1,
+        })
+    }
+
+    /// The exclusive end of the range when iterating with the provided step.
+    fn exclusive_end(&self, step: isize) -> Option<isize> {
+        self.end.map(|end| {
+            if self.inclusive {
+                end.saturating_add(step.signum())
+            } else {
+                end
+            }
+        })
+    }
+
+    // A value whose distance from the beginning of the range overflows is not contained.
+    pub(crate) fn contains(&self, value: isize) -> bool {
+        let step = self.iter_step();
+        let end = self.exclusive_end(step);
+        if step > 0 {
+            self.begin.is_none_or(|b| value >= b)
+                && end.is_none_or(|e| value < e)
+                && self.begin.is_none_or(|b| {
+                    value.checked_sub(b).and_then(|d| d.checked_rem(step)) == Some(0)
+                })
+        } else {
+            self.begin.is_none_or(|b| value <= b)
+                && end.is_none_or(|e| value > e)
+                && self.begin.is_none_or(|b| {
+                    b.checked_sub(value).and_then(|d| d.checked_rem(step)) == Some(0)
+                })
+        }
     }
 
     pub(crate) fn get_bounded_range(
         &self,
         limit: isize,
         type_name: &str,
-    ) -> Result<(usize, usize), Error> {
-        let begin = if self.begin < 0 {
-            self.begin + limit
-        } else {
-            self.begin
+    ) -> Result<BoundedRange, Error> {
+        let step = self.step.unwrap_or(1);
+        let normalise = |index: isize| if index < 0 { index + limit } else { index };
+
+        let begin = match self.begin {
+            Some(begin) => {
+                let begin = normalise(begin);
+                if begin < 0 || begin >= limit {
+                    return Err(error!(
+                        ErrorKind::IndexError,
+                        "{} slice start out of range.", type_name
+                    ));
+                }
+                begin
+            }
+            None if step > 0 => 0,
+            None => limit - 1,
         };
-        if begin < 0 || begin >= limit {
-            return Err(error!(
-                ErrorKind::IndexError,
-                "{} slice start out of range.", type_name
-            ));
-        }
-        let end = if self.end < 0 {
-            self.end + limit
+
+        let end = match self.end {
+            Some(end) => {
+                let mut end = normalise(end);
+                if self.inclusive {
+                    end += step.signum();
+                }
+                let (lower, upper) = if step > 0 {
+                    (0, limit)
+                } else {
+                    (-1, limit - 1)
+                };
+                if end < lower || end > upper {
+                    return Err(error!(
+                        ErrorKind::IndexError,
+                        "{} slice end out of range.", type_name
+                    ));
+                }
+                end
+            }
+            None if step > 0 => limit,
+            None => -1,
+        };
+
+        let end = if (step > 0 && end < begin) || (step < 0 && end > begin) {
+            begin
         } else {
-            self.end
+            end
         };
-        if end < 0 || end > limit {
-            return Err(error!(
-                ErrorKind::IndexError,
-                "{} slice end out of range.", type_name
-            ));
-        }
-        Ok((
-            begin as usize,
-            if end >= begin { end } else { begin } as usize,
-        ))
+        Ok(BoundedRange { begin, end, step })
     }
 }
 
@@ -730,14 +991,34 @@ impl memory::GcManaged for ObjRange {
 
 impl fmt::Display for ObjRange {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Range({}, {})", self.begin, self.end)
+        let fmt_bound = |bound: Option<isize>| match bound {
+            Some(value) => format!("{}", value),
+            None => String::from("nil"),
+        };
+        let name = if self.inclusive {
+            "RangeInclusive"
+        } else {
+            "Range"
+        };
+        write!(
+            f,
+            "{}({}, {}",
+            name,
+            fmt_bound(self.begin),
+            fmt_bound(self.end)
+        )?;
+        if let Some(step) = self.step {
+            write!(f, ", {}", step)?;
+        }
+        write!(f, ")")
     }
 }
 
 pub struct ObjRangeIter {
     pub class: Gc<ObjClass>,
     pub iterable: Gc<ObjRange>,
-    current: isize,
+    current: Option<isize>,
+    end: Option<isize>,
     step: isize,
 }
 
@@ -759,22 +1040,28 @@ pub fn new_root_obj_range_iter(
 
 impl ObjRangeIter {
     fn new(class: Gc<ObjClass>, iterable: Gc<ObjRange>) -> Self {
-        let current = iterable.begin;
+        let step = iterable.iter_step();
         ObjRangeIter {
             class,
             iterable,
-            current,
-            step: if iterable.begin < iterable.end { 1 } else { -1 },
+            current: iterable.begin,
+            end: iterable.exclusive_end(step),
+            step,
         }
     }
 
     pub(crate) fn next(&mut self) -> Value {
-        if self.current == self.iterable.end {
-            return Value::Sentinel;
+        let current = match self.current {
+            Some(current) => current,
+            None => return Value::Sentinel,
+        };
+        if let Some(end) = self.end {
+            if (self.step > 0 && current >= end) || (self.step < 0 && current <= end) {
+                return Value::Sentinel;
+            }
         }
-        let ret = Value::Number(self.current as f64);
-        self.current += self.step;
-        ret
+        self.current = current.checked_add(self.step);
+        Value::Number(current as f64)
     }
 }
 
@@ -812,7 +1099,7 @@ impl ObjHashMap {
     fn new(class: Gc<ObjClass>) -> Self {
         ObjHashMap {
             class,
-            elements: HashMap::with_hasher(BuildPassThroughHasher::default()),
+            elements: HashMap::with_hasher(BuildPassThroughHasher),
             disp_lock: Cell::new(false),
         }
     }
@@ -854,7 +1141,7 @@ impl fmt::Display for ObjHashMap {
 
 impl cmp::PartialEq for ObjHashMap {
     fn eq(&self, other: &ObjHashMap) -> bool {
-        if self as *const _ == other as *const _ {
+        if ptr::eq(self, other) {
             return true;
         }
         self.elements == other.elements
@@ -893,11 +1180,7 @@ impl ObjTuple {
             return true;
         }
         let self_lock_prev = self.self_lock.replace(true);
-        let ret = self
-            .elements
-            .iter()
-            .map(|v| v.has_hash())
-            .fold(true, |a, b| a && b);
+        let ret = self.elements.iter().all(|v| v.has_hash());
         self.self_lock.set(self_lock_prev);
         ret
     }
@@ -940,7 +1223,7 @@ impl fmt::Display for ObjTuple {
 
 impl cmp::PartialEq for ObjTuple {
     fn eq(&self, other: &ObjTuple) -> bool {
-        if self as *const _ == other as *const _ {
+        if ptr::eq(self, other) {
             return true;
         }
         self.elements == other.elements
@@ -957,7 +1240,7 @@ impl Hash for Gc<ObjTuple> {
                 v.hash(&mut hasher);
                 hasher.finish()
             })
-            .fold(0_64, |a, b| a ^ b);
+            .fold(0_u64, |a, b| a ^ b);
         state.write_u64(hash);
     }
 }
@@ -1021,9 +1304,12 @@ impl fmt::Display for ObjTupleIter {
 
 pub struct ObjModule {
     pub(crate) imported: bool,
+    pub(crate) is_package: bool,
+    pub(crate) is_native: bool,
     pub(crate) class: Gc<ObjClass>,
     pub(crate) path: Gc<ObjString>,
     pub attributes: HashMap<Gc<ObjString>, Value, BuildPassThroughHasher>,
+    pub doc: Option<Gc<ObjString>>,
 }
 
 pub(crate) fn new_gc_obj_module(
@@ -1046,9 +1332,12 @@ impl ObjModule {
     pub(crate) fn new(class: Gc<ObjClass>, path: Gc<ObjString>) -> Self {
         ObjModule {
             imported: false,
+            is_package: false,
+            is_native: false,
             class,
             path,
             attributes: new_obj_string_value_map(),
+            doc: None,
         }
     }
 }
@@ -1056,10 +1345,16 @@ impl ObjModule {
 impl memory::GcManaged for ObjModule {
     fn mark(&self) {
         self.attributes.mark();
+        if let Some(doc) = self.doc {
+            doc.mark();
+        }
     }
 
     fn blacken(&self) {
         self.attributes.blacken();
+        if let Some(doc) = self.doc {
+            doc.blacken();
+        }
     }
 }
 
@@ -14,8 +14,9 @@
  */
 
 use crate::common;
+use crate::unicode;
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
 #[repr(u32)]
 pub enum TokenKind {
     LeftParen,
@@ -27,12 +28,14 @@ pub enum TokenKind {
     Comma,
     Dot,
     DotDot,
+    DotDotEqual,
     Minus,
     MinusEqual,
     Plus,
     PlusEqual,
     Colon,
     SemiColon,
+    Newline,
     Slash,
     SlashEqual,
     Star,
@@ -46,22 +49,29 @@ pub enum TokenKind {
     Less,
     LessEqual,
     Bar,
+    QuestionDot,
+    QuestionLeftBracket,
+    QuestionQuestion,
+    At,
     Identifier,
     Str,
     Interpolation,
     Number,
     And,
+    Assert,
     CapSelf,
     Class,
     Else,
     False,
     For,
     Fn,
+    From,
     If,
     Import,
     As,
     In,
     Nil,
+    Not,
     Or,
     Return,
     Self_,
@@ -70,21 +80,19 @@ pub enum TokenKind {
     True,
     Var,
     While,
+    With,
     Error,
+    #[default]
     Eof,
 }
 
-impl Default for TokenKind {
-    fn default() -> Self {
-        TokenKind::Eof
-    }
-}
-
 #[derive(Default, Clone, PartialEq)]
 pub struct Token {
     pub kind: TokenKind,
     pub line: usize,
     pub source: String,
+    /// The text of any `///` doc comments immediately preceding the token.
+    pub doc: Option<String>,
 }
 
 impl Token {
@@ -97,6 +105,7 @@ impl Token {
             kind: Default::default(),
             line: Default::default(),
             source: String::from(source),
+            doc: None,
         }
     }
 
@@ -105,24 +114,46 @@ impl Token {
             kind: Default::default(),
             line,
             source: String::from(source),
+            doc: None,
         }
     }
 }
 
-fn is_alpha(s: &str) -> bool {
-    !s.is_empty() && s.chars().all(|c| c.is_ascii_alphabetic() || c == '_')
+fn is_identifier_start(s: &str) -> bool {
+    s.chars()
+        .next()
+        .is_some_and(|c| c == '_' || unicode::is_xid_start(c))
+}
+
+fn is_identifier_continue(s: &str) -> bool {
+    s.chars().next().is_some_and(unicode::is_xid_continue)
 }
 
 fn is_digit(s: &str) -> bool {
     !s.is_empty() && s.chars().all(|c| c.is_ascii_digit())
 }
 
+/// The kinds of bracket that can enclose a token, which determine whether a newline terminates a
+/// statement.
+#[derive(Copy, Clone, PartialEq)]
+enum Nesting {
+    /// Parentheses, square brackets, HashMap literals and string interpolations, inside which
+    /// newlines are insignificant.
+    Group,
+    /// Blocks, inside which newlines may terminate statements.
+    Block,
+}
+
 pub struct Scanner {
     source: String,
     start: usize,
     current: usize,
     line: usize,
     parantheses: Vec<usize>,
+    nesting: Vec<Nesting>,
+    previous_kind: TokenKind,
+    doc_lines: Vec<String>,
+    module_doc_lines: Vec<String>,
 }
 
 impl Scanner {
@@ -133,12 +164,49 @@ impl Scanner {
             current: 0,
             line: 1,
             parantheses: Vec::new(),
+            nesting: Vec::new(),
+            previous_kind: TokenKind::Eof,
+            doc_lines: Vec::new(),
+            module_doc_lines: Vec::new(),
         }
     }
 
     pub fn scan_token(&mut self) -> Token {
-        self.skip_whitespace();
+        let line = self.line;
+        let skipped_newline = match self.skip_whitespace() {
+            Ok(skipped_newline) => skipped_newline,
+            Err(token) => return token,
+        };
+
+        let mut token = if skipped_newline && self.newline_is_significant() {
+            Token {
+                kind: TokenKind::Newline,
+                line,
+                source: String::from("\n"),
+                doc: None,
+            }
+        } else {
+            self.next_token()
+        };
+        if token.kind != TokenKind::Newline && !self.doc_lines.is_empty() {
+            token.doc = Some(self.doc_lines.join("\n"));
+            self.doc_lines.clear();
+        }
+        self.previous_kind = token.kind;
+        token
+    }
 
+    /// Returns the text of any `//!` comments in the source, which document the module itself.
+    pub fn take_module_doc(&mut self) -> Option<String> {
+        if self.module_doc_lines.is_empty() {
+            return None;
+        }
+        let doc = self.module_doc_lines.join("\n");
+        self.module_doc_lines.clear();
+        Some(doc)
+    }
+
+    fn next_token(&mut self) -> Token {
         self.start = self.current;
 
         if self.is_at_end() {
@@ -147,7 +215,7 @@ impl Scanner {
 
         let c = self.advance();
 
-        if is_alpha(c) {
+        if is_identifier_start(c) {
             return self.identifier();
         }
         if is_digit(c) {
@@ -155,15 +223,27 @@ impl Scanner {
         }
 
         match c {
-            "(" => self.make_token(TokenKind::LeftParen),
-            ")" => self.make_token(TokenKind::RightParen),
+            "(" => {
+                self.nesting.push(Nesting::Group);
+                self.make_token(TokenKind::LeftParen)
+            }
+            ")" => {
+                self.nesting.pop();
+                self.make_token(TokenKind::RightParen)
+            }
             "{" => {
                 if let Some(count) = self.parantheses.last_mut() {
                     *count += 1;
                 }
+                self.nesting.push(if self.brace_opens_group() {
+                    Nesting::Group
+                } else {
+                    Nesting::Block
+                });
                 self.make_token(TokenKind::LeftBrace)
             }
             "}" => {
+                self.nesting.pop();
                 if let Some(count) = self.parantheses.last_mut() {
                     *count -= 1;
                     if *count == 0 {
@@ -176,17 +256,26 @@ impl Scanner {
                     self.make_token(TokenKind::RightBrace)
                 }
             }
-            "[" => self.make_token(TokenKind::LeftBracket),
-            "]" => self.make_token(TokenKind::RightBracket),
+            "[" => {
+                self.nesting.push(Nesting::Group);
+                self.make_token(TokenKind::LeftBracket)
+            }
+            "]" => {
+                self.nesting.pop();
+                self.make_token(TokenKind::RightBracket)
+            }
             ":" => self.make_token(TokenKind::Colon),
             ";" => self.make_token(TokenKind::SemiColon),
             "," => self.make_token(TokenKind::Comma),
             "." => {
-                let match_char = self.match_char(".");
+                if !self.match_char(".") {
+                    return self.make_token(TokenKind::Dot);
+                }
+                let match_char = self.match_char("=");
                 self.make_token(if match_char {
-                    TokenKind::DotDot
+                    TokenKind::DotDotEqual
                 } else {
-                    TokenKind::Dot
+                    TokenKind::DotDot
                 })
             }
             "-" => {
@@ -254,6 +343,19 @@ impl Scanner {
                 })
             }
             "|" => self.make_token(TokenKind::Bar),
+            "?" => {
+                if self.match_char(".") {
+                    self.make_token(TokenKind::QuestionDot)
+                } else if self.match_char("[") {
+                    self.nesting.push(Nesting::Group);
+                    self.make_token(TokenKind::QuestionLeftBracket)
+                } else if self.match_char("?") {
+                    self.make_token(TokenKind::QuestionQuestion)
+                } else {
+                    self.error_token("Unexpected character: '?'.")
+                }
+            }
+            "@" => self.make_token(TokenKind::At),
             "\"" => self.string(),
             c => {
                 let msg = format!("Unexpected character: '{}'.", c);
@@ -303,6 +405,7 @@ impl Scanner {
             kind,
             line: self.line,
             source: String::from(&self.source[self.start..self.current]),
+            doc: None,
         }
     }
 
@@ -311,13 +414,17 @@ impl Scanner {
             kind: TokenKind::Error,
             line: self.line,
             source: String::from(message),
+            doc: None,
         }
     }
 
-    fn skip_whitespace(&mut self) {
+    /// Skips whitespace and comments, returning whether any newlines were skipped or an error
+    /// token if a block comment is unterminated.
+    fn skip_whitespace(&mut self) -> Result<bool, Token> {
+        let mut skipped_newline = false;
         loop {
             if self.is_at_end() {
-                return;
+                return Ok(skipped_newline);
             }
             let c = self.peek();
             match c {
@@ -333,23 +440,140 @@ impl Scanner {
                 "\n" => {
                     self.line += 1;
                     self.advance();
+                    skipped_newline = true;
                 }
-                "/" => {
-                    if self.peek_next() == "/" {
-                        while !self.is_at_end() && self.peek() != "\n" {
-                            self.advance();
-                        }
-                    } else {
-                        return;
-                    }
+                "/" if self.peek_next() == "/" => {
+                    self.line_comment();
+                }
+                "/" if self.peek_next() == "*" => {
+                    skipped_newline |= self.block_comment()?;
                 }
                 _ => {
-                    return;
+                    return Ok(skipped_newline);
                 }
             };
         }
     }
 
+    // Doc comments start with exactly three slashes and document the following declaration,
+    // whilst those starting with `//!` document the enclosing module.
+    fn line_comment(&mut self) {
+        let begin = self.current;
+        while !self.is_at_end() && self.peek() != "\n" {
+            self.advance();
+        }
+        let comment = &self.source[begin..self.current];
+        let (lines, text) = if let Some(text) = comment.strip_prefix("//!") {
+            (&mut self.module_doc_lines, text)
+        } else if let Some(text) = comment.strip_prefix("///").filter(|t| !t.starts_with('/')) {
+            (&mut self.doc_lines, text)
+        } else {
+            return;
+        };
+        let text = text.strip_prefix(' ').unwrap_or(text);
+        lines.push(String::from(text.trim_end()));
+    }
+
+    /// Skips a possibly nested block comment, returning whether it spanned multiple lines.
+    fn block_comment(&mut self) -> Result<bool, Token> {
+        let mut depth = 0;
+        let mut skipped_newline = false;
+        loop {
+            if self.is_at_end() {
+                return Err(self.error_token("Unterminated block comment."));
+            }
+            if self.peek() == "/" && self.peek_next() == "*" {
+                depth += 1;
+                self.advance();
+            } else if self.peek() == "*" && self.peek_next() == "/" {
+                depth -= 1;
+                self.advance();
+            } else if self.peek() == "\n" {
+                self.line += 1;
+                skipped_newline = true;
+            }
+            self.advance();
+            if depth == 0 {
+                return Ok(skipped_newline);
+            }
+        }
+    }
+
+    // A newline terminates a statement if it follows a token that can end one, isn't enclosed in
+    // a group and the next line doesn't start with an infix operator.
+    fn newline_is_significant(&self) -> bool {
+        if self.nesting.last() == Some(&Nesting::Group) {
+            return false;
+        }
+        let ends_statement = matches!(
+            self.previous_kind,
+            TokenKind::Identifier
+                | TokenKind::Str
+                | TokenKind::Number
+                | TokenKind::Nil
+                | TokenKind::True
+                | TokenKind::False
+                | TokenKind::Self_
+                | TokenKind::CapSelf
+                | TokenKind::Return
+                | TokenKind::RightParen
+                | TokenKind::RightBracket
+                | TokenKind::RightBrace
+                | TokenKind::DotDot
+        );
+        ends_statement && !self.line_continues()
+    }
+
+    // A leading '-', '(', '[' or '{' starts a new statement rather than continuing the previous
+    // line, as each can begin an expression or block of its own.
+    fn line_continues(&self) -> bool {
+        const OPERATORS: [&str; 11] = [".", "?.", "?[", "??", "=", "!=", "<", ">", "+", "*", "/"];
+        const KEYWORDS: [&str; 4] = ["and", "in", "not", "or"];
+
+        let rest = &self.source[self.current..];
+        OPERATORS.iter().any(|op| rest.starts_with(op))
+            || KEYWORDS.iter().any(|keyword| {
+                rest.strip_prefix(keyword)
+                    .is_some_and(|tail| !tail.starts_with(unicode::is_xid_continue))
+            })
+    }
+
+    // A brace opens a HashMap literal rather than a block if it follows a token after which an
+    // expression is expected.
+    fn brace_opens_group(&self) -> bool {
+        matches!(
+            self.previous_kind,
+            TokenKind::LeftParen
+                | TokenKind::LeftBracket
+                | TokenKind::QuestionLeftBracket
+                | TokenKind::Comma
+                | TokenKind::Colon
+                | TokenKind::Equal
+                | TokenKind::MinusEqual
+                | TokenKind::PlusEqual
+                | TokenKind::SlashEqual
+                | TokenKind::StarEqual
+                | TokenKind::Minus
+                | TokenKind::Plus
+                | TokenKind::Slash
+                | TokenKind::Star
+                | TokenKind::Bang
+                | TokenKind::BangEqual
+                | TokenKind::EqualEqual
+                | TokenKind::Greater
+                | TokenKind::GreaterEqual
+                | TokenKind::Less
+                | TokenKind::LessEqual
+                | TokenKind::QuestionQuestion
+                | TokenKind::And
+                | TokenKind::Or
+                | TokenKind::Not
+                | TokenKind::In
+                | TokenKind::Return
+                | TokenKind::Interpolation
+        )
+    }
+
     fn check_keyword(&self, start: usize, rest: &str, kind: TokenKind) -> TokenKind {
         let slice_begin = self.start + start;
         let slice_end = slice_begin + rest.len();
@@ -369,7 +593,12 @@ impl Scanner {
                 if self.current - self.start > 1 {
                     let next = &self.source[self.start + 1..self.start + 2];
                     return match next {
-                        "s" => self.check_keyword(2, "", TokenKind::As),
+                        "s" => match self.check_keyword(2, "", TokenKind::As) {
+                            TokenKind::Identifier => {
+                                self.check_keyword(2, "sert", TokenKind::Assert)
+                            }
+                            kind => kind,
+                        },
                         "n" => self.check_keyword(2, "d", TokenKind::And),
                         _ => TokenKind::Identifier,
                     };
@@ -385,6 +614,7 @@ impl Scanner {
                         "a" => self.check_keyword(2, "lse", TokenKind::False),
                         "o" => self.check_keyword(2, "r", TokenKind::For),
                         "n" => self.check_keyword(2, "", TokenKind::Fn),
+                        "r" => self.check_keyword(2, "om", TokenKind::From),
                         _ => TokenKind::Identifier,
                     };
                 }
@@ -402,7 +632,17 @@ impl Scanner {
                 }
                 TokenKind::Identifier
             }
-            "n" => self.check_keyword(1, "il", TokenKind::Nil),
+            "n" => {
+                if self.current - self.start > 1 {
+                    let next = &self.source[self.start + 1..self.start + 2];
+                    return match next {
+                        "i" => self.check_keyword(2, "l", TokenKind::Nil),
+                        "o" => self.check_keyword(2, "t", TokenKind::Not),
+                        _ => TokenKind::Identifier,
+                    };
+                }
+                TokenKind::Identifier
+            }
             "o" => self.check_keyword(1, "r", TokenKind::Or),
             "r" => self.check_keyword(1, "eturn", TokenKind::Return),
             "S" => self.check_keyword(1, "elf", TokenKind::CapSelf),
@@ -420,15 +660,37 @@ impl Scanner {
             }
             "t" => self.check_keyword(1, "rue", TokenKind::True),
             "v" => self.check_keyword(1, "ar", TokenKind::Var),
-            "w" => self.check_keyword(1, "hile", TokenKind::While),
+            "w" => {
+                if self.current - self.start > 1 {
+                    let next = &self.source[self.start + 1..self.start + 2];
+                    return match next {
+                        "h" => self.check_keyword(2, "ile", TokenKind::While),
+                        "i" => self.check_keyword(2, "th", TokenKind::With),
+                        _ => TokenKind::Identifier,
+                    };
+                }
+                TokenKind::Identifier
+            }
             _ => TokenKind::Identifier,
         }
     }
 
     fn identifier(&mut self) -> Token {
-        while is_alpha(self.peek()) || is_digit(self.peek()) {
+        while is_identifier_continue(self.peek()) {
             self.advance();
         }
+
+        // Keywords are all ASCII, so other identifiers skip the keyword lookup. Normalising these
+        // identifiers ensures that visually identical names refer to the same variable.
+        let source = &self.source[self.start..self.current];
+        if !source.is_ascii() {
+            return Token {
+                kind: TokenKind::Identifier,
+                line: self.line,
+                source: unicode::to_nfc(source),
+                doc: None,
+            };
+        }
         self.make_token(self.identifier_type())
     }
 
@@ -499,22 +761,24 @@ impl Scanner {
                         return self.error_token("Max interpolation depth exceeded.");
                     }
                     self.parantheses.push(1);
+                    self.nesting.push(Nesting::Group);
                     return Token {
                         line: self.line,
                         source: buffer,
                         kind: TokenKind::Interpolation,
+                        doc: None,
                     };
                 }
                 "\\" => {
                     let s = self.advance();
                     match s {
-                        "$" => buffer.push_str("$"),
-                        "a" => buffer.push_str("\x07"),
-                        "b" => buffer.push_str("\x08"),
-                        "f" => buffer.push_str("\x0c"),
-                        "n" => buffer.push_str("\n"),
-                        "r" => buffer.push_str("\r"),
-                        "t" => buffer.push_str("\t"),
+                        "$" => buffer.push('$'),
+                        "a" => buffer.push('\x07'),
+                        "b" => buffer.push('\x08'),
+                        "f" => buffer.push('\x0c'),
+                        "n" => buffer.push('\n'),
+                        "r" => buffer.push('\r'),
+                        "t" => buffer.push('\t'),
                         "u" => {
                             let result = self.read_escaped_bytes(2);
                             match result {
@@ -533,7 +797,7 @@ impl Scanner {
                                 }
                             }
                         }
-                        "v" => buffer.push_str("\x0b"),
+                        "v" => buffer.push('\x0b'),
                         "x" => {
                             let result = self.read_escaped_bytes(1);
                             match result {
@@ -543,9 +807,9 @@ impl Scanner {
                                 }
                             }
                         }
-                        "\"" => buffer.push_str("\""),
-                        "\\" => buffer.push_str("\\"),
-                        "0" => buffer.push_str("\0"),
+                        "\"" => buffer.push('"'),
+                        "\\" => buffer.push('\\'),
+                        "0" => buffer.push('\0'),
                         _ => {
                             return self.error_token("Invalid escape sequence.");
                         }
@@ -571,6 +835,7 @@ impl Scanner {
             line: self.line,
             source: buffer,
             kind: TokenKind::Str,
+            doc: None,
         }
     }
 
@@ -0,0 +1,174 @@
+/* Copyright 2020-2021 Matt Spraggs
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::cmp::Ordering;
+
+use crate::unicode_tables::{
+    CANONICAL_COMBINING_CLASS, CANONICAL_COMPOSITION, CANONICAL_DECOMPOSITION, XID_CONTINUE,
+    XID_START,
+};
+
+const HANGUL_S_BASE: u32 = 0xAC00;
+const HANGUL_L_BASE: u32 = 0x1100;
+const HANGUL_V_BASE: u32 = 0x1161;
+const HANGUL_T_BASE: u32 = 0x11A7;
+const HANGUL_L_COUNT: u32 = 19;
+const HANGUL_V_COUNT: u32 = 21;
+const HANGUL_T_COUNT: u32 = 28;
+const HANGUL_N_COUNT: u32 = HANGUL_V_COUNT * HANGUL_T_COUNT;
+const HANGUL_S_COUNT: u32 = HANGUL_L_COUNT * HANGUL_N_COUNT;
+
+fn in_ranges(c: char, ranges: &[(char, char)]) -> bool {
+    ranges
+        .binary_search_by(|&(begin, end)| {
+            if end < c {
+                Ordering::Less
+            } else if begin > c {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+pub(crate) fn is_xid_start(c: char) -> bool {
+    if c.is_ascii() {
+        return c.is_ascii_alphabetic();
+    }
+    in_ranges(c, XID_START)
+}
+
+pub(crate) fn is_xid_continue(c: char) -> bool {
+    if c.is_ascii() {
+        return c.is_ascii_alphanumeric() || c == '_';
+    }
+    in_ranges(c, XID_CONTINUE)
+}
+
+fn combining_class(c: char) -> u8 {
+    if c.is_ascii() {
+        return 0;
+    }
+    CANONICAL_COMBINING_CLASS
+        .binary_search_by(|&(begin, end, _)| {
+            if end < c {
+                Ordering::Less
+            } else if begin > c {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        })
+        .map_or(0, |index| CANONICAL_COMBINING_CLASS[index].2)
+}
+
+fn decompose(c: char, output: &mut Vec<char>) {
+    let code = c as u32;
+    if (HANGUL_S_BASE..HANGUL_S_BASE + HANGUL_S_COUNT).contains(&code) {
+        let index = code - HANGUL_S_BASE;
+        let l = HANGUL_L_BASE + index / HANGUL_N_COUNT;
+        let v = HANGUL_V_BASE + (index % HANGUL_N_COUNT) / HANGUL_T_COUNT;
+        let t = HANGUL_T_BASE + index % HANGUL_T_COUNT;
+        output.extend([l, v].iter().filter_map(|&c| char::from_u32(c)));
+        if t != HANGUL_T_BASE {
+            output.extend(char::from_u32(t));
+        }
+        return;
+    }
+
+    match CANONICAL_DECOMPOSITION.binary_search_by_key(&c, |&(k, _)| k) {
+        Ok(index) => output.extend_from_slice(CANONICAL_DECOMPOSITION[index].1),
+        Err(_) => output.push(c),
+    }
+}
+
+fn compose_pair(first: char, second: char) -> Option<char> {
+    let (first_code, second_code) = (first as u32, second as u32);
+    if (HANGUL_L_BASE..HANGUL_L_BASE + HANGUL_L_COUNT).contains(&first_code)
+        && (HANGUL_V_BASE..HANGUL_V_BASE + HANGUL_V_COUNT).contains(&second_code)
+    {
+        let l_index = first_code - HANGUL_L_BASE;
+        let v_index = second_code - HANGUL_V_BASE;
+        return char::from_u32(
+            HANGUL_S_BASE + (l_index * HANGUL_V_COUNT + v_index) * HANGUL_T_COUNT,
+        );
+    }
+    if (HANGUL_S_BASE..HANGUL_S_BASE + HANGUL_S_COUNT).contains(&first_code)
+        && (HANGUL_T_BASE + 1..HANGUL_T_BASE + HANGUL_T_COUNT).contains(&second_code)
+    {
+        // Only an LV syllable, which has no trailing consonant, composes with one.
+        let t_index = (first_code - HANGUL_S_BASE) % HANGUL_T_COUNT;
+        if t_index == 0 {
+            return char::from_u32(first_code + second_code - HANGUL_T_BASE);
+        }
+    }
+
+    CANONICAL_COMPOSITION
+        .binary_search_by_key(&(first, second), |&(pair, _)| pair)
+        .ok()
+        .map(|index| CANONICAL_COMPOSITION[index].1)
+}
+
+/// Converts the provided string to Unicode Normalization Form C.
+pub(crate) fn to_nfc(source: &str) -> String {
+    if source.is_ascii() {
+        return String::from(source);
+    }
+
+    let mut chars = Vec::with_capacity(source.len());
+    for c in source.chars() {
+        decompose(c, &mut chars);
+    }
+
+    // Put each run of non-starters into canonical order. The sort is stable, so characters with
+    // the same combining class keep their relative order.
+    let mut begin = 0;
+    while begin < chars.len() {
+        if combining_class(chars[begin]) == 0 {
+            begin += 1;
+            continue;
+        }
+        let mut end = begin;
+        while end < chars.len() && combining_class(chars[end]) != 0 {
+            end += 1;
+        }
+        chars[begin..end].sort_by_key(|&c| combining_class(c));
+        begin = end;
+    }
+
+    let mut composed: Vec<char> = Vec::with_capacity(chars.len());
+    let mut starter: Option<usize> = None;
+    let mut last_class = 0;
+    for c in chars {
+        let class = combining_class(c);
+        if let Some(index) = starter {
+            let blocked = composed.len() - 1 > index && (last_class == 0 || last_class >= class);
+            if !blocked {
+                if let Some(pair) = compose_pair(composed[index], c) {
+                    composed[index] = pair;
+                    continue;
+                }
+            }
+        }
+        if class == 0 {
+            starter = Some(composed.len());
+        }
+        last_class = class;
+        composed.push(c);
+    }
+
+    composed.into_iter().collect()
+}
@@ -34,6 +34,12 @@ pub(crate) fn validate_integer(value: Value) -> Result<isize, Error> {
     }
 }
 
+/// Returns whether the provided module attribute name is private to its module. Names with a
+/// leading underscore are private, except for special names such as `__doc__`.
+pub(crate) fn is_private_name(name: &str) -> bool {
+    name.starts_with('_') && !(name.len() > 4 && name.starts_with("__") && name.ends_with("__"))
+}
+
 pub(crate) fn hash_number(num: f64) -> u64 {
     let mut hash = u64::from_ne_bytes(num.to_ne_bytes()) as u128;
     hash = (!hash).wrapping_add(hash.wrapping_shl(18));
@@ -22,13 +22,13 @@ use crate::class_store::CoreClassStore;
 use crate::hash::PassThroughHasher;
 use crate::memory::{self, Gc};
 use crate::object::{
-    ObjBoundMethod, ObjClass, ObjClosure, ObjFunction, ObjHashMap, ObjInstance, ObjModule,
-    ObjNative, ObjRange, ObjRangeIter, ObjString, ObjStringIter, ObjTuple, ObjTupleIter, ObjVec,
-    ObjVecIter,
+    ObjBoundMethod, ObjClass, ObjClosure, ObjForeign, ObjFunction, ObjHashMap, ObjInstance,
+    ObjModule, ObjNative, ObjRange, ObjRangeIter, ObjString, ObjStringIter, ObjTuple, ObjTupleIter,
+    ObjVec, ObjVecIter,
 };
 use crate::utils;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Default)]
 pub enum Value {
     Boolean(bool),
     Number(f64),
@@ -49,6 +49,8 @@ pub enum Value {
     ObjRangeIter(Gc<RefCell<ObjRangeIter>>),
     ObjHashMap(Gc<RefCell<ObjHashMap>>),
     ObjModule(Gc<RefCell<ObjModule>>),
+    ObjForeign(Gc<RefCell<ObjForeign>>),
+    #[default]
     None,
     Sentinel,
 }
@@ -83,11 +85,27 @@ impl Value {
             Value::ObjRangeIter(iter) => iter.borrow().class,
             Value::ObjHashMap(hash_map) => hash_map.borrow().class,
             Value::ObjModule(module) => module.borrow().class,
+            Value::ObjForeign(foreign) => foreign.borrow().class,
             Value::None => class_store.get_nil_class(),
             Value::Sentinel => class_store.get_sentinel_class(),
         }
     }
 
+    /// Returns the documentation attached to the value, if any. Instances share the
+    /// documentation of their class.
+    pub(crate) fn get_doc(&self) -> Option<Gc<ObjString>> {
+        match self {
+            Value::ObjFunction(function) => function.doc,
+            Value::ObjClosure(closure) => closure.borrow().function.doc,
+            Value::ObjClass(class) => class.doc,
+            Value::ObjInstance(instance) => instance.borrow().class.doc,
+            Value::ObjBoundMethod(bound) => bound.borrow().method.borrow().function.doc,
+            Value::ObjModule(module) => module.borrow().doc,
+            Value::ObjForeign(foreign) => foreign.borrow().class.doc,
+            _ => None,
+        }
+    }
+
     pub(crate) fn has_hash(&self) -> bool {
         match self {
             Value::Boolean(_) => true,
@@ -223,11 +241,12 @@ impl Value {
             _ => None,
         }
     }
-}
 
-impl Default for Value {
-    fn default() -> Self {
-        Value::None
+    pub fn try_as_obj_foreign(&self) -> Option<Gc<RefCell<ObjForeign>>> {
+        match self {
+            Value::ObjForeign(inner) => Some(*inner),
+            _ => None,
+        }
     }
 }
 
@@ -251,6 +270,7 @@ impl memory::GcManaged for Value {
             Value::ObjRangeIter(inner) => inner.mark(),
             Value::ObjHashMap(inner) => inner.mark(),
             Value::ObjModule(inner) => inner.mark(),
+            Value::ObjForeign(inner) => inner.mark(),
             _ => {}
         }
     }
@@ -274,6 +294,7 @@ impl memory::GcManaged for Value {
             Value::ObjRangeIter(inner) => inner.blacken(),
             Value::ObjHashMap(inner) => inner.blacken(),
             Value::ObjModule(inner) => inner.blacken(),
+            Value::ObjForeign(inner) => inner.blacken(),
             _ => {}
         }
     }
@@ -328,6 +349,9 @@ impl fmt::Display for Value {
             Value::ObjRangeIter(underlying) => write!(f, "{}", *underlying.borrow()),
             Value::ObjHashMap(underlying) => write!(f, "{}", *underlying.borrow()),
             Value::ObjModule(underlying) => write!(f, "<{}>", *underlying.borrow()),
+            Value::ObjForeign(underlying) => {
+                write!(f, "<{} @ {:p}>", *underlying.borrow(), underlying.as_ptr())
+            }
             Value::None => write!(f, "nil"),
             Value::Sentinel => write!(f, "<sentinel>"),
         }
@@ -359,6 +383,7 @@ impl cmp::PartialEq for Value {
                 *first.borrow() == *second.borrow()
             }
             (Value::ObjModule(first), Value::ObjModule(second)) => *first == *second,
+            (Value::ObjForeign(first), Value::ObjForeign(second)) => *first == *second,
             (Value::Sentinel, Value::Sentinel) => true,
             (Value::None, Value::None) => true,
             _ => false,
@@ -385,7 +410,12 @@ impl Hash for Value {
                 hasher.finish()
             }
             Value::ObjRange(r) => {
-                utils::hash_number(r.begin as f64) ^ utils::hash_number(r.end as f64)
+                let hash_bound =
+                    |b: Option<isize>| b.map_or(2_u64, |v| utils::hash_number(v as f64));
+                hash_bound(r.begin)
+                    ^ hash_bound(r.end).rotate_left(1)
+                    ^ hash_bound(r.step).rotate_left(2)
+                    ^ r.inclusive as u64
             }
             Value::None => 2_u64,
             Value::Sentinel => 3_u64,
@@ -16,26 +16,28 @@
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fmt::Write;
-use std::fs;
 use std::hash::{Hash, Hasher};
 use std::io;
-use std::path::Path;
 use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time;
 
-use crate::chunk::{Chunk, OpCode};
+use crate::chunk::{self, Chunk, OpCode};
 use crate::class_store::{self, CoreClassStore};
 use crate::common;
 use crate::compiler;
+use crate::convert::IntoNative;
 use crate::core;
 use crate::debug;
 use crate::error::{Error, ErrorKind};
 use crate::hash::{BuildPassThroughHasher, FnvHasher};
 use crate::memory::{self, Gc, GcBoxPtr, GcManaged, Heap, Root};
+use crate::module_loader::{join_module_paths, FileSystemLoader, ModuleLoader};
 use crate::object::{
-    self, NativeFn, ObjClass, ObjClosure, ObjFunction, ObjHashMap, ObjModule, ObjNative, ObjRange,
-    ObjRangeIter, ObjString, ObjStringIter, ObjStringValueMap, ObjTuple, ObjTupleIter, ObjUpvalue,
-    ObjVec, ObjVecIter,
+    self, Foreign, NativeFn, NativeFunction, ObjClass, ObjClosure, ObjForeign, ObjFunction,
+    ObjHashMap, ObjModule, ObjNative, ObjRange, ObjRangeIter, ObjString, ObjStringIter,
+    ObjStringValueMap, ObjTuple, ObjTupleIter, ObjUpvalue, ObjVec, ObjVecIter,
 };
 use crate::stack::Stack;
 use crate::utils;
@@ -43,8 +45,6 @@ use crate::value::Value;
 
 const RANGE_CACHE_SIZE: usize = 8;
 
-type LoadModuleFn = fn(&str) -> Result<String, Error>;
-
 pub fn interpret(vm: &mut Vm, source: String, module_path: Option<&str>) -> Result<Value, Error> {
     let compile_result = compiler::compile(vm, source, module_path);
     match compile_result {
@@ -53,10 +53,47 @@ pub fn interpret(vm: &mut Vm, source: String, module_path: Option<&str>) -> Resu
     }
 }
 
+fn check_module_access(module: Gc<RefCell<ObjModule>>, name: Gc<ObjString>) -> Result<(), Error> {
+    if utils::is_private_name(&name) {
+        return Err(error!(
+            ErrorKind::AttributeError,
+            "Cannot access private name '{}' of {}.",
+            *name,
+            *module.borrow()
+        ));
+    }
+    Ok(())
+}
+
+fn uninitialised_name_error(module: Gc<RefCell<ObjModule>>, name: Gc<ObjString>) -> Error {
+    error!(
+        ErrorKind::NameError,
+        "Name '{}' is not defined yet as {} has not finished importing.",
+        *name,
+        *module.borrow()
+    )
+}
+
+/// Interrupts the execution of a `Vm`, which may be running on another thread.
+#[derive(Clone)]
+pub struct InterruptHandle {
+    interrupted: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    /// Requests that the running script stops with an InterruptError at the next loop iteration
+    /// or function call. An interrupt requested while no script is running is discarded when the
+    /// next script starts.
+    pub fn interrupt(&self) {
+        self.interrupted.store(true, Ordering::Relaxed);
+    }
+}
+
 pub struct CallFrame {
     closure: Gc<RefCell<ObjClosure>>,
     prev_ip: *const u8,
     slot_base: usize,
+    with_slots: Vec<usize>,
 }
 
 impl GcManaged for CallFrame {
@@ -70,19 +107,31 @@ impl GcManaged for CallFrame {
 }
 
 struct ClassDef {
+    // The number of frames on the call stack when the class was declared.
+    frame_depth: usize,
     name: Gc<ObjString>,
     metaclass_name: Gc<ObjString>,
     superclass: Gc<ObjClass>,
+    metaclass: Option<Gc<ObjClass>>,
+    doc: Option<Gc<ObjString>>,
     methods: ObjStringValueMap,
     static_methods: ObjStringValueMap,
 }
 
 impl ClassDef {
-    fn new(name: Gc<ObjString>, metaclass_name: Gc<ObjString>, superclass: Gc<ObjClass>) -> Self {
+    fn new(
+        frame_depth: usize,
+        name: Gc<ObjString>,
+        metaclass_name: Gc<ObjString>,
+        superclass: Gc<ObjClass>,
+    ) -> Self {
         ClassDef {
+            frame_depth,
             name,
             metaclass_name,
             superclass,
+            metaclass: None,
+            doc: None,
             methods: object::new_obj_string_value_map(),
             static_methods: object::new_obj_string_value_map(),
         }
@@ -91,60 +140,107 @@ impl ClassDef {
 
 impl GcManaged for ClassDef {
     fn mark(&self) {
+        if let Some(metaclass) = self.metaclass {
+            metaclass.mark();
+        }
+        if let Some(doc) = self.doc {
+            doc.mark();
+        }
         self.methods.mark();
         self.static_methods.mark();
     }
 
     fn blacken(&self) {
+        if let Some(metaclass) = self.metaclass {
+            metaclass.blacken();
+        }
+        if let Some(doc) = self.doc {
+            doc.blacken();
+        }
         self.methods.blacken();
         self.static_methods.blacken();
     }
 }
 
-fn default_read_module_source(path: &str) -> Result<String, Error> {
-    let path = Path::new(path).with_extension("yl");
-    let filename = match path.as_path().to_str() {
-        Some(p) => p,
-        None => {
-            return Err(error!(
-                ErrorKind::RuntimeError,
-                "Error converting module path to string."
-            ));
-        }
-    };
-
-    let source = match fs::read_to_string(filename) {
-        Ok(s) => s,
-        Err(e) => {
-            let reason = match e.kind() {
-                io::ErrorKind::NotFound => "file not found",
-                io::ErrorKind::PermissionDenied => "permission denied",
-                io::ErrorKind::ConnectionRefused => "connection refused",
-                io::ErrorKind::ConnectionReset => "connection reset",
-                io::ErrorKind::ConnectionAborted => "connection aborted",
-                io::ErrorKind::NotConnected => "not connected",
-                io::ErrorKind::AddrInUse => "address in use",
-                io::ErrorKind::AddrNotAvailable => "address not available",
-                io::ErrorKind::BrokenPipe => "broken pipe",
-                io::ErrorKind::AlreadyExists => "already exists",
-                io::ErrorKind::WouldBlock => "would block",
-                io::ErrorKind::InvalidInput => "invalid input",
-                io::ErrorKind::InvalidData => "invalid data",
-                io::ErrorKind::TimedOut => "timed out",
-                io::ErrorKind::WriteZero => "write zero",
-                io::ErrorKind::Interrupted => "interrupted",
-                io::ErrorKind::Other => "other",
-                io::ErrorKind::UnexpectedEof => "unexpected end-of-file",
-                _ => "other",
-            };
-            return Err(error!(
-                crate::error::ErrorKind::RuntimeError,
-                "Unable to read file '{}' ({}).", filename, reason
-            ));
-        }
-    };
+/// Adds functions, classes and constants to a module implemented in Rust.
+pub struct NativeModuleBuilder<'a> {
+    vm: &'a mut Vm,
+    path: String,
+}
+
+impl NativeModuleBuilder<'_> {
+    pub fn function(&mut self, name: &str, function: NativeFn) -> &mut Self {
+        self.vm.define_native(&self.path, name, function);
+        self
+    }
+
+    pub fn closure(
+        &mut self,
+        name: &str,
+        function: impl FnMut(&mut Vm, usize) -> Result<Value, Error> + 'static,
+    ) -> &mut Self {
+        self.vm.define_native_closure(&self.path, name, function);
+        self
+    }
+
+    pub fn typed_function<Args>(
+        &mut self,
+        name: &str,
+        function: impl IntoNative<Args>,
+    ) -> &mut Self {
+        self.vm.define_typed_native(&self.path, name, function);
+        self
+    }
+
+    pub fn constant(&mut self, name: &str, value: Value) -> &mut Self {
+        self.vm.set_global(&self.path, name, value);
+        self
+    }
+
+    pub fn string(&mut self, name: &str, value: &str) -> &mut Self {
+        let value = self.vm.new_gc_obj_string(value);
+        self.constant(name, Value::ObjString(value))
+    }
+
+    /// Adds a class deriving from Object with the provided methods and static methods. The class
+    /// is instantiated by calling an `__init__` method, which must return the new instance or a
+    /// foreign object of the class.
+    pub fn class(
+        &mut self,
+        name: &str,
+        methods: &[(&str, NativeFn)],
+        static_methods: &[(&str, NativeFn)],
+    ) -> &mut Self {
+        let vm = &mut *self.vm;
+        let class_name = vm.new_gc_obj_string(name);
+        let metaclass_name = vm.new_gc_obj_string(format!("{}Class", name).as_str());
+        let object_class = vm.class_store.get_object_class();
+
+        let (static_methods, _static_roots) = core::build_methods(vm, static_methods, None);
+        let metaclass = object::new_root_obj_class(
+            vm,
+            metaclass_name,
+            vm.class_store.get_base_metaclass(),
+            Some(object_class.metaclass),
+            static_methods,
+        );
+        let (methods, _roots) = core::build_methods(vm, methods, None);
+        let class = object::new_root_obj_class(
+            vm,
+            class_name,
+            metaclass.as_gc(),
+            Some(object_class),
+            methods,
+        );
+        vm.set_global(&self.path, name, Value::ObjClass(class.as_gc()));
+        self
+    }
+}
 
-    Ok(source)
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 pub struct Vm {
@@ -155,7 +251,13 @@ pub struct Vm {
     stack: Stack<Value>,
     open_upvalues: Vec<Gc<RefCell<ObjUpvalue>>>,
     init_string: Gc<ObjString>,
+    call_string: Gc<ObjString>,
+    doc_string: Gc<ObjString>,
+    iter_string: Gc<ObjString>,
     next_string: Gc<ObjString>,
+    contains_string: Gc<ObjString>,
+    enter_string: Gc<ObjString>,
+    exit_string: Gc<ObjString>,
     pub(crate) class_store: CoreClassStore,
     chunks: Vec<Root<Chunk>>,
     modules: HashMap<Gc<ObjString>, Root<RefCell<ObjModule>>, BuildPassThroughHasher>,
@@ -164,9 +266,18 @@ pub struct Vm {
     /// TODO: Rewrite the string store to prevent key collisions.
     string_store: HashMap<u64, Root<ObjString>, BuildPassThroughHasher>,
     range_cache: Vec<(Root<ObjRange>, time::Instant)>,
-    working_class_def: Option<ClassDef>,
-    module_loader: LoadModuleFn,
-    printer: NativeFn,
+    // Classes whose bodies are being executed, innermost last. Class bodies nest when a decorator
+    // or metaclass expression defines a class of its own.
+    working_class_defs: Vec<ClassDef>,
+    module_loader: Box<dyn ModuleLoader>,
+    search_paths: Vec<String>,
+    output: Box<dyn io::Write>,
+    error_output: Box<dyn io::Write>,
+    strip_asserts: bool,
+    fuel: Option<u64>,
+    deadline: Option<time::Instant>,
+    limit_checks: u32,
+    interrupted: Arc<AtomicBool>,
     pub(crate) heap: Heap,
 }
 
@@ -184,18 +295,31 @@ impl Vm {
             stack: Stack::new(),
             open_upvalues: Vec::new(),
             init_string: unsafe { Gc::dangling() },
+            call_string: unsafe { Gc::dangling() },
+            doc_string: unsafe { Gc::dangling() },
+            iter_string: unsafe { Gc::dangling() },
             next_string: unsafe { Gc::dangling() },
+            contains_string: unsafe { Gc::dangling() },
+            enter_string: unsafe { Gc::dangling() },
+            exit_string: unsafe { Gc::dangling() },
             class_store: unsafe { CoreClassStore::new_empty() },
             chunks: Vec::new(),
-            modules: HashMap::with_hasher(BuildPassThroughHasher::default()),
+            modules: HashMap::with_hasher(BuildPassThroughHasher),
             core_chunks: Vec::new(),
             string_class: unsafe { Root::dangling() },
-            string_store: HashMap::with_hasher(BuildPassThroughHasher::default()),
+            string_store: HashMap::with_hasher(BuildPassThroughHasher),
             heap,
             range_cache: Vec::with_capacity(RANGE_CACHE_SIZE),
-            module_loader: default_read_module_source,
-            printer: core::print,
-            working_class_def: None,
+            module_loader: Box::new(FileSystemLoader::new()),
+            search_paths: vec![String::new()],
+            output: Box::new(io::stdout()),
+            error_output: Box::new(io::stderr()),
+            strip_asserts: false,
+            fuel: None,
+            deadline: None,
+            limit_checks: 0,
+            interrupted: Arc::new(AtomicBool::new(false)),
+            working_class_defs: Vec::new(),
         };
         vm.init_heap_allocated_data();
         vm
@@ -207,25 +331,128 @@ impl Vm {
         vm
     }
 
-    pub fn set_printer(&mut self, printer: NativeFn) {
-        self.printer = printer;
-        self.define_native("main", "print", self.printer);
+    /// Sets the sink written to by `print`. By default, output is written to stdout.
+    pub fn set_output(&mut self, output: impl io::Write + 'static) {
+        self.output = Box::new(output);
+    }
+
+    /// Sets the sink written to by `eprint`. By default, errors are written to stderr.
+    pub fn set_error_output(&mut self, error_output: impl io::Write + 'static) {
+        self.error_output = Box::new(error_output);
+    }
+
+    pub fn output(&mut self) -> &mut dyn io::Write {
+        self.output.as_mut()
+    }
+
+    pub fn error_output(&mut self) -> &mut dyn io::Write {
+        self.error_output.as_mut()
+    }
+
+    pub fn set_module_loader(&mut self, loader: impl ModuleLoader + 'static) {
+        self.module_loader = Box::new(loader);
+    }
+
+    /// Sets the directories searched, in order, for imported modules. By default, modules are
+    /// found relative to the current working directory.
+    pub fn set_search_paths(&mut self, paths: Vec<String>) {
+        self.search_paths = paths;
+    }
+
+    /// Adds a directory to the end of the list of directories searched for imported modules.
+    pub fn add_search_path(&mut self, path: &str) {
+        self.search_paths.push(String::from(path));
+    }
+
+    /// Limits the amount of code that can be executed, where each loop iteration and function call
+    /// uses one unit of fuel. Execution stops with a FuelError once the fuel runs out.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
     }
 
-    pub fn set_module_loader(&mut self, loader: fn(&str) -> Result<String, Error>) {
-        self.module_loader = loader;
+    /// Returns the fuel remaining, if execution is limited.
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Sets a time after which execution stops with a TimeoutError.
+    pub fn set_deadline(&mut self, deadline: Option<time::Instant>) {
+        self.deadline = deadline;
+    }
+
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        InterruptHandle {
+            interrupted: self.interrupted.clone(),
+        }
+    }
+
+    /// Sets whether assert statements are removed when compiling source code.
+    pub fn set_strip_asserts(&mut self, strip_asserts: bool) {
+        self.strip_asserts = strip_asserts;
+    }
+
+    pub(crate) fn strip_asserts(&self) -> bool {
+        self.strip_asserts
     }
 
     pub fn execute(&mut self, function: Root<ObjFunction>, args: &[Value]) -> Result<Value, Error> {
+        self.discard_stale_interrupt();
         let module = self.get_module(&function.module_path);
+        if function.doc.is_some() {
+            module.borrow_mut().doc = function.doc;
+        }
         let closure = object::new_gc_obj_closure(self, function.as_gc(), module);
         self.push(Value::ObjClosure(closure));
         self.stack.extend_from_slice(args);
+        let base_depth = self.frames.len();
         self.call_value(Value::ObjClosure(closure), args.len())?;
-        match self.run() {
-            Ok(value) => Ok(value),
-            Err(mut error) => Err(self.runtime_error(&mut error)),
-        }
+        self.run(base_depth)
+    }
+
+    /// Calls the provided function, class or callable instance, running the call to completion
+    /// before returning the result. Can be used from within native functions.
+    pub fn call(&mut self, callee: Value, args: &[Value]) -> Result<Value, Error> {
+        self.push(callee);
+        self.stack.extend_from_slice(args);
+        self.run_call(args.len(), |vm| vm.call_value(callee, args.len()))
+    }
+
+    /// Calls the global with the provided name in the provided module. See `call`.
+    pub fn call_global(
+        &mut self,
+        module_name: &str,
+        var_name: &str,
+        args: &[Value],
+    ) -> Result<Value, Error> {
+        // The arguments are pushed before looking up the callee, as the lookup may trigger a
+        // garbage collection.
+        self.push(Value::None);
+        self.stack.extend_from_slice(args);
+        self.run_call(args.len(), |vm| {
+            let callee = vm.get_global(module_name, var_name).ok_or_else(|| {
+                error!(
+                    ErrorKind::RuntimeError,
+                    "Undefined variable '{}'.", var_name
+                )
+            })?;
+            *vm.peek_mut(args.len()) = callee;
+            vm.call_value(callee, args.len())
+        })
+    }
+
+    /// Invokes the method with the provided name on the provided receiver. See `call`.
+    pub fn invoke_method(
+        &mut self,
+        receiver: Value,
+        name: &str,
+        args: &[Value],
+    ) -> Result<Value, Error> {
+        self.push(receiver);
+        self.stack.extend_from_slice(args);
+        self.run_call(args.len(), |vm| {
+            let name = vm.new_gc_obj_string(name);
+            vm.invoke(name, args.len())
+        })
     }
 
     pub fn get_global(&mut self, module_name: &str, var_name: &str) -> Option<Value> {
@@ -254,6 +481,49 @@ impl Vm {
             .insert(var_name, Value::ObjNative(native.as_gc()));
     }
 
+    /// Defines a native function backed by a closure, allowing it to own host state. The
+    /// closure cannot be called recursively.
+    pub fn define_native_closure(
+        &mut self,
+        module_name: &str,
+        var_name: &str,
+        function: impl FnMut(&mut Vm, usize) -> Result<Value, Error> + 'static,
+    ) {
+        let var_name = self.new_gc_obj_string(var_name);
+        let native = object::new_root_obj_native_closure(self, var_name, function);
+        self.get_module(module_name)
+            .borrow_mut()
+            .attributes
+            .insert(var_name, Value::ObjNative(native.as_gc()));
+    }
+
+    /// Defines a native function wrapping a Rust function whose arguments and return value are
+    /// converted to and from script values automatically.
+    pub fn define_typed_native<Args>(
+        &mut self,
+        module_name: &str,
+        var_name: &str,
+        function: impl IntoNative<Args>,
+    ) {
+        self.define_native_closure(module_name, var_name, function.into_native());
+    }
+
+    /// Creates a module implemented in Rust with the provided path, returning a builder used to
+    /// populate the module. Importing the path binds the module without consulting the module
+    /// loader.
+    pub fn define_native_module(&mut self, path: &str) -> NativeModuleBuilder<'_> {
+        let module = self.get_module(path);
+        {
+            let mut module = module.borrow_mut();
+            module.imported = true;
+            module.is_native = true;
+        }
+        NativeModuleBuilder {
+            vm: self,
+            path: String::from(path),
+        }
+    }
+
     pub fn get_class(&self, value: Value) -> Gc<ObjClass> {
         value.get_class(&self.class_store)
     }
@@ -285,8 +555,35 @@ impl Vm {
         object::new_root_obj_hash_map(self, class)
     }
 
+    pub fn new_root_obj_foreign(
+        &mut self,
+        class: Gc<ObjClass>,
+        data: impl Foreign,
+    ) -> Root<RefCell<ObjForeign>> {
+        object::new_root_obj_foreign(self, class, data)
+    }
+
+    /// Returns the receiver of a native method call, raising a TypeError if it isn't a foreign
+    /// object wrapping a `T`.
+    pub fn foreign_receiver<T: Foreign>(
+        &self,
+        num_args: usize,
+    ) -> Result<Gc<RefCell<ObjForeign>>, Error> {
+        let receiver = *self.peek(num_args);
+        match receiver.try_as_obj_foreign() {
+            Some(foreign) if foreign.borrow().is::<T>() => Ok(foreign),
+            _ => Err(error!(
+                ErrorKind::TypeError,
+                "Expected a foreign '{}' receiver but found '{}'.",
+                std::any::type_name::<T>(),
+                receiver
+            )),
+        }
+    }
+
     pub fn new_root_obj_range(&mut self, begin: isize, end: isize) -> Root<ObjRange> {
-        self.build_range(begin, end).as_root()
+        self.build_range(Some(begin), Some(end), None, false)
+            .as_root()
     }
 
     pub fn new_root_obj_range_iter(&mut self, range: Gc<ObjRange>) -> Root<RefCell<ObjRangeIter>> {
@@ -349,16 +646,26 @@ impl Vm {
         self.chunks[index].as_gc()
     }
 
+    /// Frees all unreachable objects, finalising any foreign objects among them.
+    pub fn collect_garbage(&mut self) {
+        let roots: Vec<&dyn GcManaged> = vec![
+            &self.stack,
+            &self.modules,
+            &self.frames,
+            &self.open_upvalues,
+            &self.working_class_defs,
+        ];
+        self.heap.collect(&roots);
+    }
+
     pub(crate) fn allocate_bare<T: 'static + GcManaged>(&mut self, data: T) -> GcBoxPtr<T> {
-        let mut roots: Vec<&dyn GcManaged> = vec![
+        let roots: Vec<&dyn GcManaged> = vec![
             &self.stack,
             &self.modules,
             &self.frames,
             &self.open_upvalues,
+            &self.working_class_defs,
         ];
-        if let Some(def) = self.working_class_def.as_ref() {
-            roots.push(def);
-        }
         self.heap.allocate_bare(&roots, data)
     }
 
@@ -367,20 +674,26 @@ impl Vm {
     }
 
     pub(crate) fn allocate_root<T: 'static + GcManaged>(&mut self, data: T) -> Root<T> {
-        let mut roots: Vec<&dyn GcManaged> = vec![
+        let roots: Vec<&dyn GcManaged> = vec![
             &self.stack,
             &self.modules,
             &self.frames,
             &self.open_upvalues,
+            &self.working_class_defs,
         ];
-        if let Some(def) = self.working_class_def.as_ref() {
-            roots.push(def);
-        }
         self.heap.allocate_root(&roots, data)
     }
 
-    fn run(&mut self) -> Result<Value, Error> {
-        debug_assert!(self.modules.len() == 1);
+    /// Runs the bytecode of the active frame until the call stack unwinds to `base_depth` frames,
+    /// at which point the value returned by the last frame is returned.
+    fn run(&mut self, base_depth: usize) -> Result<Value, Error> {
+        match self.run_frames(base_depth) {
+            Ok(value) => Ok(value),
+            Err(error) => Err(self.unwind(base_depth, error)),
+        }
+    }
+
+    fn run_frames(&mut self, base_depth: usize) -> Result<Value, Error> {
         macro_rules! binary_op {
             ($value_type:expr, $op:tt) => {
                 {
@@ -473,6 +786,13 @@ impl Vm {
                     self.push(top);
                 }
 
+                byte if byte == OpCode::CopyTopTwo as u8 => {
+                    let first = *self.peek(1);
+                    let second = *self.peek(0);
+                    self.push(first);
+                    self.push(second);
+                }
+
                 byte if byte == OpCode::GetLocal as u8 => {
                     let slot = read_byte!() as usize;
                     let slot_base = self.frame().slot_base;
@@ -560,11 +880,22 @@ impl Vm {
                         }
                     }
                     if let Some(module) = self.peek(0).try_as_obj_module() {
+                        check_module_access(module, name)?;
                         if let Some(&property) = module.borrow().attributes.get(&name) {
                             self.pop();
                             self.push(property);
                             continue;
                         }
+                        if !module.borrow().imported {
+                            return Err(uninitialised_name_error(module, name));
+                        }
+                    }
+
+                    if name == self.doc_string {
+                        let doc = self.peek(0).get_doc();
+                        self.pop();
+                        self.push(doc.map_or(Value::None, Value::ObjString));
+                        continue;
                     }
 
                     let class = self.peek(0).get_class(&self.class_store);
@@ -574,6 +905,7 @@ impl Vm {
                 byte if byte == OpCode::SetProperty as u8 => {
                     if let Some(module) = self.peek(1).try_as_obj_module() {
                         let name = read_string!();
+                        check_module_access(module, name)?;
                         let value = *self.peek(0);
                         module.borrow_mut().attributes.insert(name, value);
                         self.pop();
@@ -629,6 +961,22 @@ impl Vm {
 
                 byte if byte == OpCode::Less as u8 => binary_op!(Value::Boolean, <),
 
+                byte if byte == OpCode::Contains as u8 => {
+                    let container = *self.peek(0);
+                    let value = *self.peek(1);
+                    let class = self.get_class(container);
+                    if class.methods.contains_key(&self.contains_string) {
+                        *self.peek_mut(1) = container;
+                        *self.peek_mut(0) = value;
+                        self.invoke_from_class(class, self.contains_string, 1)?;
+                        continue;
+                    }
+                    let found = self.iter_contains(container, value)?;
+                    self.pop();
+                    self.pop();
+                    self.push(Value::Boolean(found));
+                }
+
                 byte if byte == OpCode::Add as u8 => {
                     let b = self.pop();
                     let a = self.pop();
@@ -709,9 +1057,21 @@ impl Vm {
                 }
 
                 byte if byte == OpCode::BuildRange as u8 => {
-                    let end = utils::validate_integer(self.pop())?;
-                    let begin = utils::validate_integer(self.pop())?;
-                    let range = self.build_range(begin, end);
+                    let flags = read_byte!();
+                    let mut pop_component = |flag: u8| -> Result<Option<isize>, Error> {
+                        if flags & flag == 0 {
+                            return Ok(None);
+                        }
+                        utils::validate_integer(self.pop()).map(Some)
+                    };
+                    let step = pop_component(chunk::RANGE_HAS_STEP)?;
+                    let end = pop_component(chunk::RANGE_HAS_END)?;
+                    let begin = pop_component(chunk::RANGE_HAS_BEGIN)?;
+                    if step == Some(0) {
+                        return Err(error!(ErrorKind::ValueError, "Range step cannot be zero."));
+                    }
+                    let inclusive = flags & chunk::RANGE_INCLUSIVE != 0;
+                    let range = self.build_range(begin, end, step, inclusive);
                     self.push(Value::ObjRange(range));
                 }
 
@@ -734,7 +1094,7 @@ impl Vm {
                     let num_operands = read_byte!() as usize;
                     let begin = self.stack.len() - num_operands;
                     let end = self.stack.len();
-                    let elements = self.stack[begin..end].iter().copied().collect();
+                    let elements = self.stack[begin..end].to_vec();
                     let tuple = object::new_root_obj_tuple(
                         self,
                         self.class_store.get_obj_tuple_class(),
@@ -749,7 +1109,7 @@ impl Vm {
                     let vec = object::new_root_obj_vec(self, self.class_store.get_obj_vec_class());
                     let begin = self.stack.len() - num_operands;
                     let end = self.stack.len();
-                    vec.borrow_mut().elements = self.stack[begin..end].iter().copied().collect();
+                    vec.borrow_mut().elements = self.stack[begin..end].to_vec();
                     self.stack.truncate(begin);
                     self.push(Value::ObjVec(vec.as_gc()));
                 }
@@ -772,6 +1132,13 @@ impl Vm {
                     }
                 }
 
+                byte if byte == OpCode::JumpIfNil as u8 => {
+                    let offset = read_short!();
+                    if let Value::None = self.peek(0) {
+                        self.ip = unsafe { self.ip.offset(offset as isize) };
+                    }
+                }
+
                 byte if byte == OpCode::JumpIfSentinel as u8 => {
                     let offset = read_short!();
                     if let Value::Sentinel = self.peek(0) {
@@ -781,6 +1148,7 @@ impl Vm {
 
                 byte if byte == OpCode::Loop as u8 => {
                     let offset = read_short!();
+                    self.check_limits()?;
                     self.ip = unsafe { self.ip.offset(-(offset as isize)) };
                 }
 
@@ -841,25 +1209,81 @@ impl Vm {
 
                     let prev_stack_size = self.frame().slot_base;
                     let prev_ip = self.frame().prev_ip;
+                    // A module has been imported once its top-level code has finished executing.
+                    let closure = self.frame().closure;
+                    if closure.borrow().function.name.is_empty() {
+                        closure.borrow().module.borrow_mut().imported = true;
+                    }
                     self.frames.pop();
-                    if self.frames.is_empty() {
-                        return Ok(self.pop());
+                    if !self.frames.is_empty() {
+                        let prev_chunk_index = self.frame().closure.borrow().function.chunk_index;
+                        let prev_module = self.frame().closure.borrow().module;
+                        self.active_chunk = self.get_chunk(prev_chunk_index);
+                        self.active_module = prev_module;
                     }
-                    let prev_chunk_index = self.frame().closure.borrow().function.chunk_index;
-                    let prev_module = self.frame().closure.borrow().module;
-                    self.active_chunk = self.get_chunk(prev_chunk_index);
-                    self.active_module = prev_module;
                     self.ip = prev_ip;
 
                     self.stack.truncate(prev_stack_size);
+                    if self.frames.len() == base_depth {
+                        return Ok(result);
+                    }
                     self.push(result);
                 }
 
+                byte if byte == OpCode::Assert as u8 => {
+                    let message = self.pop();
+                    return Err(match message {
+                        Value::None => error!(ErrorKind::AssertionError, "Assertion failed."),
+                        _ => error!(ErrorKind::AssertionError, "Assertion failed: {}.", message),
+                    });
+                }
+
+                byte if byte == OpCode::AssertCompare as u8 => {
+                    let op = read_string!();
+                    let message = self.pop();
+                    let second = *self.peek(1);
+                    let first = *self.peek(2);
+                    return Err(match message {
+                        Value::None => error!(
+                            ErrorKind::AssertionError,
+                            "Assertion failed: {} {} {}.", first, *op, second
+                        ),
+                        _ => error!(
+                            ErrorKind::AssertionError,
+                            "Assertion failed: {} ({} {} {}).", message, first, *op, second
+                        ),
+                    });
+                }
+
+                byte if byte == OpCode::EnterWith as u8 => {
+                    let slot = read_byte!() as usize;
+                    let manager = *self.peek(0);
+                    let value = self.invoke_and_run(manager, self.enter_string, &[])?;
+                    self.push(value);
+                    self.frame_mut().with_slots.push(slot);
+                }
+
+                byte if byte == OpCode::ExitWith as u8 => {
+                    let slot = self
+                        .frame_mut()
+                        .with_slots
+                        .pop()
+                        .expect("Expected active with block.");
+                    let manager = self.stack[self.frame().slot_base + slot];
+                    self.invoke_and_run(manager, self.exit_string, &[])?;
+                }
+
                 byte if byte == OpCode::DeclareClass as u8 => {
                     let name = read_string!();
                     let metaclass_name = self.new_gc_obj_string(format!("{}Class", *name).as_str());
                     let superclass = self.class_store.get_object_class();
-                    self.working_class_def = Some(ClassDef::new(name, metaclass_name, superclass));
+                    let frame_depth = self.frames.len();
+                    self.working_class_defs.push(ClassDef::new(
+                        frame_depth,
+                        name,
+                        metaclass_name,
+                        superclass,
+                    ));
                     let class = object::new_gc_obj_class(
                         self,
                         name,
@@ -871,31 +1295,49 @@ impl Vm {
                 }
 
                 byte if byte == OpCode::DefineClass as u8 => {
-                    let class_def = self.working_class_def.as_ref().expect("Expected ClassDef.");
+                    let class_def = self.working_class_def()?;
 
-                    let base_metaclass = self.class_store.get_base_metaclass();
                     let name = class_def.name;
                     let metaclass_name = class_def.metaclass_name;
                     let methods = class_def.methods.clone();
                     let static_methods = class_def.static_methods.clone();
                     let superclass = class_def.superclass;
+                    let doc = class_def.doc;
+                    let declared_metaclass = class_def.metaclass;
+                    let base_metaclass = self.class_store.get_base_metaclass();
+                    // Without an explicit metaclass, a class uses the metaclass declared by its
+                    // nearest ancestor, if any. The superclass's own metaclass is not used, as
+                    // static methods are not inherited.
+                    let metaclass_parent = declared_metaclass.unwrap_or_else(|| {
+                        superclass
+                            .metaclass
+                            .superclass
+                            .unwrap_or_else(|| self.class_store.get_object_class())
+                    });
                     let metaclass = object::new_root_obj_class(
                         self,
                         metaclass_name,
                         base_metaclass,
-                        Some(self.class_store.get_object_class()),
+                        Some(metaclass_parent),
                         static_methods,
                     );
 
-                    let defined_class = object::new_root_obj_class(
+                    let defined_class = object::new_gc_obj_class_with_doc(
                         self,
                         name,
                         metaclass.as_gc(),
                         Some(superclass),
                         methods,
-                    );
-                    self.working_class_def = None;
+                        doc,
+                    )
+                    .as_root();
+                    self.working_class_defs.pop();
                     *self.peek_mut(0) = Value::ObjClass(defined_class.as_gc());
+
+                    if metaclass.methods.contains_key(&self.init_string) {
+                        let class = Value::ObjClass(defined_class.as_gc());
+                        self.invoke_and_run(class, self.init_string, &[])?;
+                    }
                 }
 
                 byte if byte == OpCode::Inherit as u8 => {
@@ -907,10 +1349,37 @@ impl Vm {
                             "Superclass must be a class."
                         ));
                     };
-                    self.working_class_def.as_mut().unwrap().superclass = superclass;
+                    self.working_class_def()?.superclass = superclass;
                     self.pop();
                 }
 
+                byte if byte == OpCode::Metaclass as u8 => {
+                    let metaclass = self.pop();
+                    let base_metaclass = self.class_store.get_base_metaclass();
+                    let is_metaclass = metaclass.try_as_obj_class().is_some_and(|class| {
+                        let mut current = Some(class);
+                        while let Some(ancestor) = current {
+                            if ancestor == base_metaclass {
+                                return true;
+                            }
+                            current = ancestor.superclass;
+                        }
+                        false
+                    });
+                    if !is_metaclass {
+                        return Err(error!(
+                            ErrorKind::TypeError,
+                            "Metaclass must be a subclass of 'Type' but found '{}'.", metaclass
+                        ));
+                    }
+                    self.working_class_def()?.metaclass = metaclass.try_as_obj_class();
+                }
+
+                byte if byte == OpCode::ClassDoc as u8 => {
+                    let doc = read_string!();
+                    self.working_class_def()?.doc = Some(doc);
+                }
+
                 byte if byte == OpCode::Method as u8 => {
                     let name = read_string!();
                     self.define_method(name, false)?;
@@ -923,38 +1392,19 @@ impl Vm {
 
                 byte if byte == OpCode::StartImport as u8 => {
                     let path = read_string!();
+                    let path = self.resolve_module_path(path)?;
 
-                    if let Some(module) = self.modules.get(&path).map(|m| m.as_gc()) {
-                        if module.borrow().imported {
-                            self.push(Value::ObjModule(module));
+                    let function = self.load_module(path)?;
+                    let module = self.get_module(&path);
+                    self.push(Value::ObjModule(module));
+                    let function = match function {
+                        Some(function) => function,
+                        None => {
                             self.push(Value::None);
                             continue;
-                        } else {
-                            return Err(error!(
-                                ErrorKind::RuntimeError,
-                                "Circular dependency encountered when importing module '{}'.",
-                                path.as_str()
-                            ));
-                        }
-                    }
-
-                    let source = (self.module_loader)(&path)?;
-
-                    let function = match compiler::compile(self, source, Some(&path)) {
-                        Ok(f) => f,
-                        Err(e) => {
-                            let mut error =
-                                error!(ErrorKind::RuntimeError, "Error compiling module:");
-                            for msg in e.get_messages() {
-                                error.add_message(&format!("    {}", msg));
-                            }
-                            return Err(error);
                         }
                     };
 
-                    let module = self.get_module(&path);
-                    self.push(Value::ObjModule(module));
-
                     let closure = object::new_gc_obj_closure(self, function.as_gc(), module);
                     self.push(Value::ObjClosure(closure));
 
@@ -965,11 +1415,28 @@ impl Vm {
 
                 byte if byte == OpCode::FinishImport as u8 => {
                     self.pop();
+                }
+
+                byte if byte == OpCode::ImportFrom as u8 => {
+                    let name = read_string!();
                     let module = self
                         .peek(0)
                         .try_as_obj_module()
                         .expect("Expected ObjModule.");
-                    module.borrow_mut().imported = true;
+                    check_module_access(module, name)?;
+                    let value = module.borrow().attributes.get(&name).copied();
+                    let value = value.ok_or_else(|| {
+                        if !module.borrow().imported {
+                            return uninitialised_name_error(module, name);
+                        }
+                        error!(
+                            ErrorKind::AttributeError,
+                            "Cannot import undefined name '{}' from {}.",
+                            *name,
+                            *module.borrow()
+                        )
+                    })?;
+                    *self.peek_mut(0) = value;
                 }
 
                 _ => {
@@ -1014,6 +1481,17 @@ impl Vm {
 
             Value::ObjNative(wrapped) => self.call_native(wrapped, arg_count),
 
+            Value::ObjInstance(instance)
+                if instance
+                    .borrow()
+                    .class
+                    .methods
+                    .contains_key(&self.call_string) =>
+            {
+                let class = instance.borrow().class;
+                self.invoke_from_class(class, self.call_string, arg_count)
+            }
+
             _ => Err(error!(
                 ErrorKind::TypeError,
                 "Can only call functions and classes."
@@ -1021,6 +1499,231 @@ impl Vm {
         }
     }
 
+    /// Invokes the named method on the provided receiver, running any resulting call frame to
+    /// completion before returning the result.
+    pub(crate) fn invoke_and_run(
+        &mut self,
+        receiver: Value,
+        name: Gc<ObjString>,
+        args: &[Value],
+    ) -> Result<Value, Error> {
+        self.push(receiver);
+        self.stack.extend_from_slice(args);
+        self.run_call(args.len(), |vm| vm.invoke(name, args.len()))
+    }
+
+    // Starts a call whose callee and arguments are on the top of the stack and runs it to
+    // completion. The callee and arguments are removed from the stack if the call fails.
+    fn run_call(
+        &mut self,
+        arg_count: usize,
+        start: impl FnOnce(&mut Vm) -> Result<(), Error>,
+    ) -> Result<Value, Error> {
+        self.discard_stale_interrupt();
+        let stack_len = self.stack.len() - arg_count - 1;
+        let base_depth = self.frames.len();
+        let result = start(self).and_then(|_| self.finish_call(base_depth));
+        if result.is_err() {
+            self.stack.truncate(stack_len);
+        }
+        result
+    }
+
+    // An interrupt only applies to the script that is running when it is requested, so one left
+    // over from an earlier script is discarded when execution starts from the top level.
+    fn discard_stale_interrupt(&mut self) {
+        if self.frames.is_empty() {
+            self.interrupted.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Imports the module with the provided path, executing the module's body if the module
+    /// hasn't been imported already.
+    pub(crate) fn import_module(
+        &mut self,
+        path: Gc<ObjString>,
+    ) -> Result<Gc<RefCell<ObjModule>>, Error> {
+        let path = self.resolve_module_path(path)?;
+        let function = self.load_module(path)?;
+        let module = self.get_module(&path);
+        if let Some(function) = function {
+            self.init_built_in_globals(&path);
+            self.execute_module(function, module)?;
+        }
+        Ok(module)
+    }
+
+    // Creates the module with the provided path, returning the compiled body of the module if it
+    // still needs to be executed. A module that is still being imported is not executed again, so
+    // circular imports receive the partially initialised module.
+    fn load_module(&mut self, path: Gc<ObjString>) -> Result<Option<Root<ObjFunction>>, Error> {
+        if self.modules.contains_key(&path) {
+            return Ok(None);
+        }
+
+        let (function, is_package) = self.compile_module(path)?;
+        let module = self.get_module(&path);
+        module.borrow_mut().doc = function.doc;
+        module.borrow_mut().is_package = is_package;
+        Ok(Some(function))
+    }
+
+    // Reads and compiles the source of the module with the provided path, returning the module's
+    // top-level function and whether the module is a package.
+    fn compile_module(&mut self, path: Gc<ObjString>) -> Result<(Root<ObjFunction>, bool), Error> {
+        let (source, is_package) = self.read_module_source(&path)?;
+
+        match compiler::compile(self, source, Some(&path)) {
+            Ok(function) => Ok((function, is_package)),
+            Err(e) => {
+                let mut error = error!(ErrorKind::RuntimeError, "Error compiling module:");
+                for msg in e.get_messages() {
+                    error.add_message(&format!("    {}", msg));
+                }
+                Err(error)
+            }
+        }
+    }
+
+    // Executes the top-level code of a module, returning once the code has finished.
+    fn execute_module(
+        &mut self,
+        function: Root<ObjFunction>,
+        module: Gc<RefCell<ObjModule>>,
+    ) -> Result<(), Error> {
+        let closure = object::new_gc_obj_closure(self, function.as_gc(), module);
+        let base_depth = self.frames.len();
+        self.push(Value::ObjClosure(closure));
+        let result = self
+            .call_value(Value::ObjClosure(closure), 0)
+            .and_then(|_| self.finish_call(base_depth));
+        if result.is_err() {
+            self.forget_failed_import(module);
+        }
+        result.map(|_| ())
+    }
+
+    // Forgets a module whose top-level code failed before it finished importing, so that
+    // importing it again executes its body rather than binding the partially initialised module.
+    fn forget_failed_import(&mut self, module: Gc<RefCell<ObjModule>>) {
+        if !module.borrow().imported {
+            let path = module.borrow().path;
+            self.modules.remove(&path);
+        }
+    }
+
+    /// Recompiles the source of an imported module and executes it in the existing module, so
+    /// that existing references to the module see the new definitions. The module is left
+    /// unchanged if the source fails to compile.
+    pub fn reload_module(&mut self, path: &str) -> Result<(), Error> {
+        let path = self.new_gc_obj_string(path);
+        let module = match self.modules.get(&path) {
+            Some(module) => module.as_gc(),
+            None => {
+                return Err(error!(
+                    ErrorKind::ValueError,
+                    "Module '{}' has not been imported.",
+                    path.as_str()
+                ));
+            }
+        };
+        self.reload(module)
+    }
+
+    pub(crate) fn reload(&mut self, module: Gc<RefCell<ObjModule>>) -> Result<(), Error> {
+        let path = module.borrow().path;
+        if path.as_str() == "main" {
+            return Err(error!(
+                ErrorKind::ValueError,
+                "Cannot reload top-level module."
+            ));
+        }
+        if module.borrow().is_native {
+            return Err(error!(
+                ErrorKind::ValueError,
+                "Cannot reload native module '{}'.",
+                path.as_str()
+            ));
+        }
+
+        let (function, is_package) = self.compile_module(path)?;
+        module.borrow_mut().doc = function.doc;
+        module.borrow_mut().is_package = is_package;
+        self.execute_module(function, module)
+    }
+
+    // Resolves an import path using the module loader. Imports within a package are resolved
+    // relative to the package's index module.
+    fn resolve_module_path(&mut self, path: Gc<ObjString>) -> Result<Gc<ObjString>, Error> {
+        let importer = {
+            let module = self.active_module.borrow();
+            if module.is_package {
+                join_module_paths(&module.path, "index")
+            } else {
+                String::from(module.path.as_str())
+            }
+        };
+        let resolved = self.module_loader.resolve(&path, &importer)?;
+        Ok(self.new_gc_obj_string(&resolved))
+    }
+
+    // Reads the source of the module with the provided path from the first search path in which
+    // either the module or a package directory containing an index module is found. Returns the
+    // source and whether the module is a package.
+    fn read_module_source(&mut self, path: &str) -> Result<(String, bool), Error> {
+        let index_path = join_module_paths(path, "index");
+        let mut first_error = None;
+        for directory in &self.search_paths {
+            for (candidate, is_package) in [(path, false), (index_path.as_str(), true)] {
+                match self
+                    .module_loader
+                    .load(&join_module_paths(directory, candidate))
+                {
+                    Ok(source) => return Ok((source, is_package)),
+                    Err(error) => {
+                        first_error.get_or_insert(error);
+                    }
+                }
+            }
+        }
+        Err(first_error.unwrap_or_else(|| {
+            error!(ErrorKind::RuntimeError, "Unable to find module '{}'.", path)
+        }))
+    }
+
+    fn finish_call(&mut self, base_depth: usize) -> Result<Value, Error> {
+        if self.frames.len() > base_depth {
+            self.run(base_depth)
+        } else {
+            Ok(self.pop())
+        }
+    }
+
+    fn iter_contains(&mut self, container: Value, value: Value) -> Result<bool, Error> {
+        let class = self.get_class(container);
+        if !class.methods.contains_key(&self.iter_string) {
+            return Err(error!(
+                ErrorKind::TypeError,
+                "Expected a container or iterable but found '{}'.", container
+            ));
+        }
+        let iter = self.invoke_and_run(container, self.iter_string, &[])?;
+        // Keep the iterator on the stack so it isn't collected while iterating.
+        self.push(iter);
+        let found = loop {
+            self.check_limits()?;
+            let next = self.invoke_and_run(iter, self.next_string, &[])?;
+            if let Value::Sentinel = next {
+                break false;
+            }
+            if next == value {
+                break true;
+            }
+        };
+        self.pop();
+        Ok(found)
+    }
+
     fn invoke_from_class(
         &mut self,
         class: Gc<ObjClass>,
@@ -1111,13 +1814,21 @@ impl Vm {
                 arg_count,
             ),
             Value::ObjModule(module) => {
+                check_module_access(module, name)?;
                 let global = module.borrow().attributes.get(&name).copied();
                 if let Some(value) = global {
                     *self.peek_mut(arg_count) = value;
                     return self.call_value(value, arg_count);
                 }
+                if !module.borrow().imported {
+                    return Err(uninitialised_name_error(module, name));
+                }
                 self.invoke_from_class(module.borrow().class, name, arg_count)
             }
+            Value::ObjForeign(foreign) => {
+                let class = foreign.borrow().class;
+                self.invoke_from_class(class, name, arg_count)
+            }
             Value::None => {
                 self.invoke_from_class(self.class_store.get_nil_class(), name, arg_count)
             }
@@ -1144,6 +1855,7 @@ impl Vm {
         if self.frames.len() == common::FRAMES_MAX {
             return Err(error!(ErrorKind::IndexError, "Stack overflow."));
         }
+        self.check_limits()?;
 
         let (chunk_index, module) = {
             let borrowed_closure = closure.borrow();
@@ -1155,6 +1867,7 @@ impl Vm {
             closure,
             prev_ip: self.ip,
             slot_base: self.stack.len() - arg_count - 1,
+            with_slots: Vec::new(),
         });
         self.ip = &self.active_chunk.code[0];
         self.active_module = module;
@@ -1162,25 +1875,118 @@ impl Vm {
     }
 
     fn call_native(&mut self, native: Gc<ObjNative>, arg_count: usize) -> Result<(), Error> {
-        let function = native.function;
+        self.check_limits()?;
         let frame_end = self.stack.len();
         let frame_begin = frame_end - arg_count - 1;
-        let result = function(self, arg_count)?;
+        let result = match &native.function {
+            NativeFunction::Fn(function) => function(self, arg_count)?,
+            NativeFunction::Closure(function) => {
+                // A bound native is replaced by its receiver on the stack, so keep the native
+                // alive while its closure is borrowed.
+                let _native_root = native.as_root();
+                let mut function = function.try_borrow_mut().map_err(|_| {
+                    error!(
+                        ErrorKind::RuntimeError,
+                        "Cannot call native function '{}' recursively.",
+                        native.name.as_str()
+                    )
+                })?;
+                function(self, arg_count)?
+            }
+        };
         self.stack.truncate(frame_begin + 1);
         *self.peek_mut(0) = result;
         Ok(())
     }
 
+    // Checks the fuel, deadline and interrupt limits on execution. The deadline is only checked
+    // periodically to avoid reading the clock on every loop iteration and function call.
+    fn check_limits(&mut self) -> Result<(), Error> {
+        if let Some(fuel) = self.fuel {
+            if fuel == 0 {
+                return Err(error!(ErrorKind::FuelError, "Execution ran out of fuel."));
+            }
+            self.fuel = Some(fuel - 1);
+        }
+        if self.interrupted.load(Ordering::Relaxed) {
+            self.interrupted.store(false, Ordering::Relaxed);
+            return Err(error!(ErrorKind::InterruptError, "Execution interrupted."));
+        }
+        if let Some(deadline) = self.deadline {
+            if self.limit_checks == 0 {
+                self.limit_checks = common::DEADLINE_CHECK_INTERVAL;
+                if time::Instant::now() >= deadline {
+                    return Err(error!(
+                        ErrorKind::TimeoutError,
+                        "Execution exceeded its deadline."
+                    ));
+                }
+            }
+            self.limit_checks -= 1;
+        }
+        Ok(())
+    }
+
     fn reset_stack(&mut self) {
         self.stack.clear();
         self.frames.clear();
     }
 
-    fn runtime_error(&mut self, error: &mut Error) -> Error {
+    /// Unwinds the call frames above `base_depth` after a runtime error, exiting any active with
+    /// blocks in those frames and adding their traceback to the error. An error raised when
+    /// exiting a with block replaces the original error.
+    fn unwind(&mut self, base_depth: usize, mut error: Error) -> Error {
+        for depth in (base_depth..self.frames.len()).rev() {
+            while let Some(slot) = self.frames[depth].with_slots.pop() {
+                let manager = self.stack[self.frames[depth].slot_base + slot];
+                if let Err(exit_error) = self.invoke_and_run(manager, self.exit_string, &[]) {
+                    error = exit_error;
+                }
+            }
+        }
+
+        // Classes declared by the unwound frames will never be defined.
+        self.working_class_defs
+            .retain(|class_def| class_def.frame_depth <= base_depth);
+
+        // Module bodies above the base frame were started by imports that have now failed.
+        for depth in base_depth + 1..self.frames.len() {
+            let closure = self.frames[depth].closure;
+            if closure.borrow().function.name.is_empty() {
+                self.forget_failed_import(closure.borrow().module);
+            }
+        }
+
+        self.add_traceback(base_depth, &mut error);
+
+        let slot_base = self.frames[base_depth].slot_base;
+        let prev_ip = self.frames[base_depth].prev_ip;
+        for i in slot_base..self.stack.len() {
+            self.close_upvalues(i, self.stack[i]);
+        }
+        self.frames.truncate(base_depth);
+        self.stack.truncate(slot_base);
+        self.ip = prev_ip;
+        if let Some(frame) = self.frames.last() {
+            let (chunk_index, module) = {
+                let borrowed_closure = frame.closure.borrow();
+                (
+                    borrowed_closure.function.chunk_index,
+                    borrowed_closure.module,
+                )
+            };
+            self.active_chunk = self.get_chunk(chunk_index);
+            self.active_module = module;
+        }
+
+        error
+    }
+
+    fn add_traceback(&self, base_depth: usize, error: &mut Error) {
         let mut ips: Vec<*const u8> = self.frames.iter().skip(1).map(|f| f.prev_ip).collect();
         ips.push(self.ip);
 
-        for (i, frame) in self.frames.iter().enumerate().rev() {
+        for (i, frame) in self.frames.iter().enumerate().skip(base_depth).rev() {
             let (function, module) = {
                 let borrowed_closure = frame.closure.borrow();
                 (borrowed_closure.function, borrowed_closure.module)
@@ -1203,15 +2009,23 @@ impl Vm {
             }
             error.add_message(new_msg.as_str());
         }
+    }
 
-        self.reset_stack();
-
-        error.clone()
+    fn working_class_def(&mut self) -> Result<&mut ClassDef, Error> {
+        self.working_class_defs
+            .last_mut()
+            .ok_or_else(|| error!(ErrorKind::RuntimeError, "No class is being defined."))
     }
 
     fn define_method(&mut self, name: Gc<ObjString>, is_static: bool) -> Result<(), Error> {
         let method = *self.peek(0);
-        let class_def = self.working_class_def.as_mut().unwrap();
+        if !matches!(method, Value::ObjClosure(_) | Value::ObjNative(_)) {
+            return Err(error!(
+                ErrorKind::TypeError,
+                "Expected a function for method '{}' but found '{}'.", *name, method
+            ));
+        }
+        let class_def = self.working_class_def()?;
         class_def.methods.insert(name, method);
         if is_static {
             class_def.static_methods.insert(name, method);
@@ -1269,13 +2083,18 @@ impl Vm {
         self.open_upvalues.retain(|u| u.borrow().is_open());
     }
 
-    fn build_range(&mut self, begin: isize, end: isize) -> Gc<ObjRange> {
+    fn build_range(
+        &mut self,
+        begin: Option<isize>,
+        end: Option<isize>,
+        step: Option<isize>,
+        inclusive: bool,
+    ) -> Gc<ObjRange> {
         // Ranges are cached using a crude LRU cache. Since the cache size is small it's reasonable
         // to store the cache elements in a Vec and just iterate.
-        let result = self
-            .range_cache
-            .iter()
-            .find(|&(r, _)| r.begin == begin && r.end == end);
+        let result = self.range_cache.iter().find(|&(r, _)| {
+            r.begin == begin && r.end == end && r.step == step && r.inclusive == inclusive
+        });
 
         if let Some((range, _)) = result {
             return range.as_gc();
@@ -1283,8 +2102,14 @@ impl Vm {
 
         // Cache miss! Create the range and cache it.
 
-        let range =
-            object::new_root_obj_range(self, self.class_store.get_obj_range_class(), begin, end);
+        let range = object::new_root_obj_range(
+            self,
+            self.class_store.get_obj_range_class(),
+            begin,
+            end,
+            step,
+            inclusive,
+        );
         let range_gc = range.as_gc();
 
         // Check the cache size. If we're at the limit, evict the oldest element.
@@ -1313,6 +2138,7 @@ impl Vm {
             metaclass: root_base_metaclass.as_gc(),
             superclass: None,
             methods: object::new_obj_string_value_map(),
+            doc: None,
         });
         let root_object_class = Root::from(object_class_ptr);
         let mut string_metaclass_ptr = self.allocate_bare(ObjClass::new(
@@ -1353,10 +2179,22 @@ impl Vm {
 
         let empty_chunk = self.allocate(Chunk::new());
         let init_string = self.new_gc_obj_string("__init__");
+        let call_string = self.new_gc_obj_string("__call__");
+        let doc_string = self.new_gc_obj_string("__doc__");
+        let iter_string = self.new_gc_obj_string("iter");
         let next_string = self.new_gc_obj_string("next");
+        let contains_string = self.new_gc_obj_string("__contains__");
+        let enter_string = self.new_gc_obj_string("__enter__");
+        let exit_string = self.new_gc_obj_string("__exit__");
         self.active_chunk = empty_chunk;
         self.init_string = init_string;
+        self.call_string = call_string;
+        self.doc_string = doc_string;
+        self.iter_string = iter_string;
         self.next_string = next_string;
+        self.contains_string = contains_string;
+        self.enter_string = enter_string;
+        self.exit_string = exit_string;
         let class_store =
             CoreClassStore::new_with_built_ins(self, root_base_metaclass, root_object_class);
         self.core_chunks = self.chunks.clone();
@@ -1366,8 +2204,12 @@ impl Vm {
     fn init_built_in_globals(&mut self, module_path: &str) {
         self.define_native(module_path, "clock", core::clock);
         self.define_native(module_path, "type", core::type_);
-        self.define_native(module_path, "print", self.printer);
+        self.define_native(module_path, "print", core::print);
+        self.define_native(module_path, "eprint", core::eprint);
         self.define_native(module_path, "sentinel", core::sentinel);
+        self.define_native(module_path, "help", core::help);
+        self.define_native(module_path, "import_module", core::import_module);
+        self.define_native(module_path, "reload", core::reload);
         let base_metaclass = self.class_store.get_base_metaclass();
         self.set_global(module_path, "Type", Value::ObjClass(base_metaclass));
         let object_class = self.class_store.get_object_class();
@@ -1422,6 +2264,10 @@ impl Vm {
         self.frames.last().expect("Call stack empty.")
     }
 
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("Call stack empty.")
+    }
+
     fn peek_mut(&mut self, depth: usize) -> &mut Value {
         self.stack.peek_mut(depth)
     }
@@ -13,12 +13,20 @@
  * limitations under the License.
  */
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::fmt;
+use std::io;
 use std::mem;
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
 
+use yarel::convert::{FromValue, IntoValue};
 use yarel::error::{Error, ErrorKind};
+use yarel::memory::GcManaged;
+use yarel::module_loader::{ChainedLoader, FileSystemLoader, MemoryLoader, ModuleLoader};
+use yarel::object::Foreign;
 use yarel::value::Value;
 use yarel::vm::{self, Vm};
 
@@ -26,8 +34,6 @@ type Matcher = fn(&str) -> Option<usize>;
 
 const WILDCARDS: [(&str, Matcher); 1] = [("[MEMADDR]", match_memaddr)];
 
-thread_local!(static OUTPUT: RefCell<Vec<String>> = RefCell::new(Vec::new()));
-
 #[allow(dead_code)]
 struct Outcome {
     pass: bool,
@@ -37,13 +43,13 @@ struct Outcome {
 
 impl fmt::Display for Outcome {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Expected:\n")?;
+        writeln!(f, "Expected:")?;
         for line in &self.expected {
-            write!(f, "    {}\n", line)?;
+            writeln!(f, "    {}", line)?;
         }
-        write!(f, "Actual:\n")?;
+        writeln!(f, "Actual:")?;
         for line in &self.actual {
-            write!(f, "    {}\n", line)?;
+            writeln!(f, "    {}", line)?;
         }
         Ok(())
     }
@@ -120,18 +126,27 @@ fn parse_test(source: &str) -> Vec<String> {
     lines
 }
 
-fn local_print(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
-    if num_args != 1 {
-        return Err(Error::with_message(
-            ErrorKind::RuntimeError,
-            "Expected one argument to 'print'.",
-        ));
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn take_lines(&self) -> Vec<String> {
+        let bytes = mem::take(&mut *self.0.borrow_mut());
+        String::from_utf8_lossy(&bytes)
+            .lines()
+            .map(String::from)
+            .collect()
     }
-    let lines = format!("{}", vm.peek(0));
-    for line in lines.as_str().lines() {
-        OUTPUT.with(|output| output.borrow_mut().push(line.to_string()));
+}
+
+impl io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
     }
-    Ok(Value::None)
 }
 
 fn match_output(expected: &[String], actual: &[String]) -> bool {
@@ -155,16 +170,22 @@ fn match_output(expected: &[String], actual: &[String]) -> bool {
 #[allow(dead_code)]
 fn run_test(source: &str) -> Outcome {
     let mut vm = Vm::with_built_ins();
-    vm.set_printer(local_print);
-    vm.set_module_loader(module_loader);
+    run_test_with_vm(&mut vm, source)
+}
+
+fn run_test_with_vm(vm: &mut Vm, source: &str) -> Outcome {
+    let buffer = SharedBuffer::default();
+    vm.set_output(buffer.clone());
+    vm.set_error_output(buffer.clone());
+    vm.set_module_loader(module_loader());
 
-    let result = vm::interpret(&mut vm, source.to_string(), None);
+    let result = vm::interpret(vm, source.to_string(), None);
     let error_output = result
         .map_err(|e| e.get_messages().clone())
         .err()
         .unwrap_or_default();
 
-    let mut output = OUTPUT.with(|output| mem::take(&mut *output.borrow_mut()));
+    let mut output = buffer.take_lines();
     output.extend_from_slice(&error_output);
     let expected = parse_test(source);
 
@@ -182,7 +203,7 @@ macro_rules! test_case {
         #[test]
         fn $name() {
             let outcome = run_test($source);
-            assert!(outcome.pass, format!("\n{}", outcome),);
+            assert!(outcome.pass, "\n{}", outcome);
         }
     };
 }
@@ -190,17 +211,569 @@ macro_rules! test_case {
 #[allow(unused_macros)]
 macro_rules! gen_module_loader {
     ($($key:expr => $result:expr),*) => {
-        fn module_loader(path: &str) -> Result<String, Error> {
-            match path {
-                $($key => Ok($result.to_string()),)*
-                _ => Err(Error::with_message(
-                    ErrorKind::RuntimeError,
-                    &format!("Unable to read file '{}.yl' (file not found).", path),
-                )),
-            }
+        fn module_loader() -> MemoryLoader {
+            let mut loader = MemoryLoader::new();
+            $(loader.insert($key, $result);)*
+            loader
         }
     }
 }
 
 include!(concat!(env!("OUT_DIR"), "/module_loader.rs"));
 include!(concat!(env!("OUT_DIR"), "/compiled_tests.rs"));
+
+#[test]
+fn assert_stripped() {
+    let mut vm = Vm::with_built_ins();
+    vm.set_strip_asserts(true);
+    let source = "// done\n// 0\nassert false, \"unreachable\";\nprint(\"done\");\n";
+    let outcome = run_test_with_vm(&mut vm, source);
+    assert!(outcome.pass, "\n{}", outcome);
+}
+
+#[test]
+fn search_paths() {
+    let mut vm = Vm::with_built_ins();
+    vm.add_search_path("modules/package");
+    let source = "// 21\n// 0\nimport \"helper\";\nprint(helper.value);\n";
+    let outcome = run_test_with_vm(&mut vm, source);
+    assert!(outcome.pass, "\n{}", outcome);
+}
+
+struct SharedSourceLoader(Rc<RefCell<String>>);
+
+impl ModuleLoader for SharedSourceLoader {
+    fn load(&mut self, _path: &str) -> Result<String, Error> {
+        Ok(self.0.borrow().clone())
+    }
+}
+
+#[test]
+fn reload_module() {
+    let mut vm = Vm::with_built_ins();
+    let plugin_source = Rc::new(RefCell::new(String::new()));
+    vm.set_module_loader(SharedSourceLoader(plugin_source.clone()));
+    let set_reload_source = |source: &str| *plugin_source.borrow_mut() = source.to_string();
+
+    set_reload_source("var value = 1;\nfn get() { return value; }\n");
+    let source = "import \"plugin\";\nvar get = plugin.get;\n";
+    assert!(vm::interpret(&mut vm, source.to_string(), None).is_ok());
+
+    set_reload_source("var value = 2;\nfn get() { return value * 10; }\n");
+    assert!(vm.reload_module("plugin").is_ok());
+    let source = "assert get() == 2;\nassert plugin.get() == 20;\n";
+    assert!(vm::interpret(&mut vm, source.to_string(), None).is_ok());
+
+    set_reload_source("var value = ;\n");
+    let error = vm.reload_module("plugin").unwrap_err();
+    assert_eq!(error.get_messages()[0], "Error compiling module:");
+    assert!(matches!(
+        vm.get_global("plugin", "value"),
+        Some(Value::Number(n)) if n == 2.0
+    ));
+
+    let error = vm.reload_module("missing").unwrap_err();
+    assert_eq!(error.get_kind(), ErrorKind::ValueError);
+}
+
+#[test]
+fn chained_module_loader() {
+    let mut first = MemoryLoader::new();
+    first.insert("lib/a", "import \"./b\";\nvar value = b.value + 1;\n");
+    let mut second = MemoryLoader::new();
+    second.insert("lib/b", "var value = 41;\n");
+
+    let mut vm = Vm::with_built_ins();
+    vm.set_module_loader(ChainedLoader::new().with_loader(first).with_loader(second));
+    let source = "import \"lib/a\";\nassert a.value == 42;\n";
+    assert!(vm::interpret(&mut vm, source.to_string(), None).is_ok());
+}
+
+#[test]
+fn file_system_loader_not_found() {
+    let mut vm = Vm::with_built_ins();
+    vm.set_module_loader(FileSystemLoader::new());
+    let source = "import \"not_found\";\n";
+    let error = vm::interpret(&mut vm, source.to_string(), None)
+        .err()
+        .unwrap();
+    assert_eq!(
+        error.get_messages()[0],
+        "Unable to read file 'not_found.yl' (file not found)."
+    );
+}
+
+#[test]
+fn failed_import() {
+    let mut loader = MemoryLoader::new();
+    loader.insert("broken", "print(\"loading\");\nvar value = 1 + nil;\n");
+    let mut vm = Vm::with_built_ins();
+    vm.set_module_loader(loader);
+    let output = SharedBuffer::default();
+    vm.set_output(output.clone());
+
+    let source = "import \"broken\";\nprint(broken);\n";
+    for _ in 0..2 {
+        let error = vm::interpret(&mut vm, source.to_string(), None)
+            .err()
+            .unwrap();
+        assert_eq!(error.get_kind(), ErrorKind::RuntimeError);
+        assert_eq!(output.take_lines(), vec!["loading"]);
+    }
+}
+
+fn native_square(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    assert_eq!(num_args, 1);
+    let value = vm.peek(0).try_as_number().unwrap();
+    Ok(Value::Number(value * value))
+}
+
+fn native_point_init(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    assert_eq!(num_args, 2);
+    let (x, y) = (*vm.peek(1), *vm.peek(0));
+    let receiver = *vm.peek(2);
+    let instance = receiver.try_as_obj_instance().unwrap();
+    let x_name = vm.new_gc_obj_string("x");
+    let y_name = vm.new_gc_obj_string("y");
+    instance.borrow_mut().fields.insert(x_name, x);
+    instance.borrow_mut().fields.insert(y_name, y);
+    Ok(receiver)
+}
+
+fn native_point_sum(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    assert_eq!(num_args, 0);
+    let instance = vm.peek(0).try_as_obj_instance().unwrap();
+    let sum = instance
+        .borrow()
+        .fields
+        .values()
+        .map(|v| v.try_as_number().unwrap())
+        .sum();
+    Ok(Value::Number(sum))
+}
+
+fn native_point_origin(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    assert_eq!(num_args, 0);
+    let class = vm.peek(0).try_as_obj_class().unwrap();
+    Ok(Value::ObjString(vm.new_gc_obj_string(class.name.as_str())))
+}
+
+#[test]
+fn native_module() {
+    let mut vm = Vm::with_built_ins();
+    vm.define_native_module("math")
+        .function("square", native_square)
+        .constant("tau", Value::Number(6.0))
+        .string("name", "math")
+        .class(
+            "Point",
+            &[("__init__", native_point_init), ("sum", native_point_sum)],
+            &[("origin", native_point_origin)],
+        );
+    vm.define_native_module("host/db")
+        .constant("version", Value::Number(3.0));
+    let source = concat!(
+        "// 9\n// 6\n// math\n// 7\n// Point\n// 3\n",
+        "// Cannot reload native module 'math'.\n",
+        "// [module \"main\", line 18] in script\n// 70\n",
+        "import \"math\";\n",
+        "from \"host/db\" import version;\n",
+        "print(math.square(3));\n",
+        "print(math.tau);\n",
+        "print(math.name);\n",
+        "print(math.Point(3, 4).sum());\n",
+        "print(math.Point.origin());\n",
+        "print(version);\n",
+        "reload(math);\n",
+    );
+    let outcome = run_test_with_vm(&mut vm, source);
+    assert!(outcome.pass, "\n{}", outcome);
+}
+
+#[test]
+fn native_closure() {
+    let mut vm = Vm::with_built_ins();
+    let calls = Rc::new(RefCell::new(Vec::new()));
+    let recorded = calls.clone();
+    vm.define_native_closure("main", "record", move |vm, num_args| {
+        assert_eq!(num_args, 1);
+        recorded
+            .borrow_mut()
+            .push(vm.peek(0).try_as_number().unwrap());
+        Ok(Value::Number(recorded.borrow().len() as f64))
+    });
+    let mut total = 0.0;
+    vm.define_native_module("host/stats")
+        .closure("accumulate", move |vm, _| {
+            total += vm.peek(0).try_as_number().unwrap();
+            Ok(Value::Number(total))
+        });
+    let source = concat!(
+        "// 1\n// 2\n// 3\n// 5\n// 0\n",
+        "print(record(4));\n",
+        "print(record(8));\n",
+        "import \"host/stats\";\n",
+        "var accumulate = stats.accumulate;\n",
+        "print(accumulate(3));\n",
+        "print(accumulate(2));\n",
+    );
+    let outcome = run_test_with_vm(&mut vm, source);
+    assert!(outcome.pass, "\n{}", outcome);
+    assert_eq!(*calls.borrow(), vec![4.0, 8.0]);
+
+    vm.define_native_closure("main", "reenter", |vm, num_args| {
+        assert_eq!(num_args, 1);
+        let callback = *vm.peek(0);
+        vm.call(callback, &[])
+    });
+    let source = "fn again() { return reenter(again); }\nreenter(again);\n";
+    let error = vm::interpret(&mut vm, source.to_string(), None)
+        .err()
+        .unwrap();
+    assert_eq!(
+        error.get_messages()[0],
+        "Cannot call native function 'reenter' recursively."
+    );
+}
+
+#[test]
+fn bound_native_closure() {
+    let mut vm = Vm::with_built_ins();
+    let mut count = 0.0;
+    vm.define_native_closure("main", "tally", move |vm, num_args| {
+        assert_eq!(num_args, 0);
+        assert!(vm.peek(0).try_as_obj_instance().is_some());
+        count += 1.0;
+        Ok(Value::Number(count))
+    });
+    let source = concat!(
+        "// 1\n// 2\n// 0\n",
+        "class Widget {\n",
+        "    @|f| tally\n",
+        "    static fn tally() {}\n",
+        "}\n",
+        "var widget = Widget();\n",
+        "var bound = widget.tally;\n",
+        "print(bound());\n",
+        "print(widget.tally());\n",
+    );
+    let outcome = run_test_with_vm(&mut vm, source);
+    assert!(outcome.pass, "\n{}", outcome);
+}
+
+thread_local!(static FINALISED: Cell<usize> = const { Cell::new(0) });
+
+struct Counter {
+    count: f64,
+    label: Value,
+}
+
+impl GcManaged for Counter {
+    fn mark(&self) {
+        self.label.mark();
+    }
+
+    fn blacken(&self) {
+        self.label.blacken();
+    }
+}
+
+impl Foreign for Counter {
+    fn finalise(&mut self) {
+        FINALISED.with(|finalised| finalised.set(finalised.get() + 1));
+    }
+}
+
+fn native_counter_init(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    assert_eq!(num_args, 1);
+    let class = vm.get_class(*vm.peek(1));
+    let label = *vm.peek(0);
+    let counter = vm.new_root_obj_foreign(class, Counter { count: 0.0, label });
+    Ok(Value::ObjForeign(counter.as_gc()))
+}
+
+fn native_counter_increment(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    let counter = vm.foreign_receiver::<Counter>(num_args)?;
+    let mut counter = counter.borrow_mut();
+    let counter = counter.downcast_mut::<Counter>().unwrap();
+    counter.count += 1.0;
+    Ok(Value::Number(counter.count))
+}
+
+fn native_counter_label(vm: &mut Vm, num_args: usize) -> Result<Value, Error> {
+    let counter = vm.foreign_receiver::<Counter>(num_args)?;
+    let label = counter.borrow().downcast_ref::<Counter>().unwrap().label;
+    Ok(label)
+}
+
+#[test]
+fn foreign_object() {
+    let mut vm = Vm::with_built_ins();
+    vm.define_native_module("counters").class(
+        "Counter",
+        &[
+            ("__init__", native_counter_init),
+            ("increment", native_counter_increment),
+            ("label", native_counter_label),
+        ],
+        &[],
+    );
+    let source = concat!(
+        "// 2\n// 3\n// <Counter instance @ [MEMADDR]>\n// true\n// 0\n",
+        "import \"counters\";\n",
+        "var counter = counters.Counter([\"cli\", \"cks\"]);\n",
+        "counter.increment();\n",
+        "var increment = counter.increment;\n",
+        "print(increment());\n",
+        "print(counter.increment());\n",
+        "print(counter);\n",
+        "print(counter == counter);\n",
+        "counters.Counter([\"discarded\"]);\n",
+    );
+    let outcome = run_test_with_vm(&mut vm, source);
+    assert!(outcome.pass, "\n{}", outcome);
+
+    // The label is only reachable through the foreign object that holds it.
+    vm.collect_garbage();
+    assert_eq!(FINALISED.with(Cell::get), 1);
+    let source = "// [cli, cks]\n// 0\nprint(counter.label());\n";
+    let outcome = run_test_with_vm(&mut vm, source);
+    assert!(outcome.pass, "\n{}", outcome);
+
+    drop(vm);
+    assert_eq!(FINALISED.with(Cell::get), 2);
+}
+
+fn typed_repeat(count: i64, text: &str) -> Result<String, Error> {
+    if count < 0 {
+        return Err(Error::with_message(
+            ErrorKind::ValueError,
+            "Expected a non-negative count.",
+        ));
+    }
+    Ok(text.repeat(count as usize))
+}
+
+#[test]
+fn typed_native() {
+    let mut vm = Vm::with_built_ins();
+    vm.define_typed_native("main", "repeat", typed_repeat);
+    vm.define_native_module("stats")
+        .typed_function("total", |values: Vec<f64>| Ok(values.iter().sum::<f64>()))
+        .typed_function("split", |pair: (String, Option<u8>)| {
+            Ok(vec![pair.0; pair.1.unwrap_or(1) as usize])
+        })
+        .typed_function("counts", |words: Vec<String>| {
+            let mut counts = HashMap::new();
+            for word in words {
+                *counts.entry(word).or_insert(0) += 1;
+            }
+            Ok(counts)
+        })
+        .typed_function("positions", |words: Vec<String>| {
+            Ok(words
+                .into_iter()
+                .enumerate()
+                .map(|(index, word)| ((word, Some(index)), index))
+                .collect::<HashMap<_, _>>())
+        });
+    let source = concat!(
+        "// abab\n// 6\n// [x, x]\n// [y]\n// 2\n// 1\n// 0\n",
+        "import \"stats\";\n",
+        "print(repeat(2, \"ab\"));\n",
+        "print(stats.total([1, 2, 3]));\n",
+        "print(stats.split((\"x\", 2)));\n",
+        "print(stats.split((\"y\", nil)));\n",
+        "print(stats.counts([\"a\", \"b\", \"a\"]).get(\"a\"));\n",
+        "print(stats.positions([\"a\", \"b\"]).get((\"b\", 1)));\n",
+    );
+    let outcome = run_test_with_vm(&mut vm, source);
+    assert!(outcome.pass, "\n{}", outcome);
+
+    let errors = [
+        ("repeat(\"a\", \"b\");", "Expected a number but found 'a'."),
+        (
+            "repeat(1.5, \"b\");",
+            "Expected an integer representable as i64 but found '1.5'.",
+        ),
+        ("repeat(1);", "Expected 2 parameters but found 1."),
+        ("repeat(-1, \"b\");", "Expected a non-negative count."),
+    ];
+    for (source, message) in errors {
+        let error = vm::interpret(&mut vm, source.to_string(), None)
+            .err()
+            .unwrap();
+        assert_eq!(error.get_messages()[0], message);
+    }
+
+    let value = vec![(1_i32, String::from("one"))].into_value(&mut vm);
+    let converted = Vec::<(i32, String)>::from_value(&value).unwrap();
+    assert_eq!(converted, vec![(1, String::from("one"))]);
+
+    let number = |n: f64| Value::Number(n);
+    assert!(i64::from_value(&number(-2f64.powi(63))).is_ok());
+    assert!(i64::from_value(&number(2f64.powi(63))).is_err());
+    assert!(u64::from_value(&number(2f64.powi(63))).is_ok());
+    assert!(u64::from_value(&number(2f64.powi(64))).is_err());
+    assert!(u8::from_value(&number(255.0)).is_ok());
+    assert!(u8::from_value(&number(256.0)).is_err());
+    assert!(usize::from_value(&number(-1.0)).is_err());
+}
+
+#[test]
+fn call_from_rust() {
+    let mut vm = Vm::with_built_ins();
+    vm.define_native_closure("main", "apply", |vm, num_args| {
+        assert_eq!(num_args, 2);
+        let (callback, arg) = (*vm.peek(1), *vm.peek(0));
+        vm.call(callback, &[arg])
+    });
+    let source = concat!(
+        "fn add(a, b) { return a + b; }\n",
+        "fn fail() {\n",
+        "  return nil + 1;\n",
+        "}\n",
+        "class Greeter {\n",
+        "  fn __init__(self, name) { self.name = name; }\n",
+        "  fn greet(self, greeting) { return greeting + \", \" + self.name; }\n",
+        "}\n",
+        "var greeter = Greeter(\"world\");\n",
+        "var doubled = apply(|x| x * 2, 21);\n",
+    );
+    assert!(vm::interpret(&mut vm, source.to_string(), None).is_ok());
+
+    let result = vm.call_global("main", "add", &[Value::Number(1.0), Value::Number(2.0)]);
+    assert!(matches!(result, Ok(Value::Number(n)) if n == 3.0));
+    assert!(matches!(
+        vm.get_global("main", "doubled"),
+        Some(Value::Number(n)) if n == 42.0
+    ));
+
+    let greeter = vm.get_global("main", "greeter").unwrap();
+    let greeting = "Hello".into_value(&mut vm);
+    let result = vm
+        .invoke_method(greeter, "greet", &[greeting])
+        .ok()
+        .unwrap();
+    assert_eq!(String::from_value(&result).unwrap(), "Hello, world");
+
+    let add = vm.get_global("main", "add").unwrap();
+    let error = vm.call(add, &[Value::Number(1.0)]).err().unwrap();
+    assert_eq!(error.get_messages()[0], "Expected 2 arguments but found 1.");
+
+    let error = vm.call_global("main", "fail", &[]).err().unwrap();
+    assert_eq!(
+        error.get_messages(),
+        &[
+            "Binary operands must be two numbers or two strings.",
+            "[module \"main\", line 3] in fail()",
+        ]
+    );
+
+    let error = vm.call_global("main", "missing", &[]).err().unwrap();
+    assert_eq!(error.get_messages()[0], "Undefined variable 'missing'.");
+
+    let result = vm.call_global("main", "apply", &[add, Value::Number(1.0)]);
+    assert_eq!(
+        result.err().unwrap().get_messages()[0],
+        "Expected 2 arguments but found 1."
+    );
+    let result = vm.call(add, &[Value::Number(2.0), Value::Number(2.0)]);
+    assert!(matches!(result, Ok(Value::Number(n)) if n == 4.0));
+}
+
+#[test]
+fn output_sinks() {
+    let mut vm = Vm::with_built_ins();
+    let (output, error_output) = (SharedBuffer::default(), SharedBuffer::default());
+    vm.set_output(output.clone());
+    vm.set_error_output(error_output.clone());
+    let source = "print(\"out\");\neprint(\"err\");\nhelp(1);\n";
+    assert!(vm::interpret(&mut vm, source.to_string(), None).is_ok());
+    assert_eq!(
+        output.take_lines(),
+        vec!["out", "1", "    No documentation."]
+    );
+    assert_eq!(error_output.take_lines(), vec!["err"]);
+}
+
+#[test]
+fn execution_fuel() {
+    let mut vm = Vm::with_built_ins();
+    vm.set_fuel(Some(10));
+    let source = "for i in 0..3 {}\n";
+    assert!(vm::interpret(&mut vm, source.to_string(), None).is_ok());
+    assert!(vm.fuel().unwrap() < 10);
+
+    let source = "while true {}\n";
+    let error = vm::interpret(&mut vm, source.to_string(), None)
+        .err()
+        .unwrap();
+    assert_eq!(error.get_kind(), ErrorKind::FuelError);
+    assert_eq!(
+        error.get_messages(),
+        &[
+            "Execution ran out of fuel.",
+            "[module \"main\", line 1] in script",
+        ]
+    );
+    assert_eq!(vm.fuel(), Some(0));
+
+    // Membership tests iterate in native code, which must still consume fuel.
+    vm.set_fuel(Some(1000));
+    let source = "class C { fn iter(self) { return (0..).iter(); } }\nprint(-1 in C());\n";
+    let error = vm::interpret(&mut vm, source.to_string(), None)
+        .err()
+        .unwrap();
+    assert_eq!(error.get_kind(), ErrorKind::FuelError);
+
+    vm.set_fuel(None);
+    assert!(vm::interpret(&mut vm, "for i in 0..3 {}\n".to_string(), None).is_ok());
+}
+
+#[test]
+fn execution_deadline() {
+    let mut vm = Vm::with_built_ins();
+    vm.set_deadline(Some(Instant::now() + Duration::from_millis(50)));
+    let source = "fn spin() { while true {} }\nspin();\n";
+    let error = vm::interpret(&mut vm, source.to_string(), None)
+        .err()
+        .unwrap();
+    assert_eq!(error.get_kind(), ErrorKind::TimeoutError);
+
+    vm.set_deadline(Some(Instant::now() + Duration::from_millis(50)));
+    let source = "print(-1 in (0..).iter());\n";
+    let error = vm::interpret(&mut vm, source.to_string(), None)
+        .err()
+        .unwrap();
+    assert_eq!(error.get_kind(), ErrorKind::TimeoutError);
+
+    vm.set_deadline(None);
+    let output = SharedBuffer::default();
+    vm.set_output(output.clone());
+    assert!(vm::interpret(&mut vm, "print(1);\n".to_string(), None).is_ok());
+    assert_eq!(output.take_lines(), vec!["1"]);
+}
+
+#[test]
+fn execution_interrupt() {
+    let mut vm = Vm::with_built_ins();
+    let handle = vm.interrupt_handle();
+    let stale_handle = handle.clone();
+    let interrupter = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        handle.interrupt();
+    });
+    let error = vm::interpret(&mut vm, "while true {}\n".to_string(), None)
+        .err()
+        .unwrap();
+    interrupter.join().unwrap();
+    assert_eq!(error.get_kind(), ErrorKind::InterruptError);
+    assert_eq!(error.get_messages()[0], "Execution interrupted.");
+
+    assert!(vm::interpret(&mut vm, "for i in 0..3 {}\n".to_string(), None).is_ok());
+
+    // An interrupt requested between scripts doesn't stop the next one.
+    stale_handle.interrupt();
+    assert!(vm::interpret(&mut vm, "for i in 0..3 {}\n".to_string(), None).is_ok());
+}
@@ -0,0 +1,116 @@
+#!/usr/bin/env python3
+"""Generates src/unicode_tables.rs from the Unicode database bundled with Python.
+
+Usage: python3 tools/generate_unicode_tables.py > src/unicode_tables.rs
+"""
+
+import sys
+import unicodedata
+
+HANGUL_BASE = 0xAC00
+HANGUL_COUNT = 11172
+MAX_CODE_POINT = 0x110000
+
+
+def is_surrogate(code):
+    return 0xD800 <= code <= 0xDFFF
+
+
+def code_points():
+    return (code for code in range(MAX_CODE_POINT) if not is_surrogate(code))
+
+
+def ranges(predicate):
+    result = []
+    for code in code_points():
+        if not predicate(code):
+            continue
+        if result and result[-1][1] == code - 1:
+            result[-1][1] = code
+        else:
+            result.append([code, code])
+    return result
+
+
+def class_ranges():
+    result = []
+    for code in code_points():
+        combining_class = unicodedata.combining(chr(code))
+        if combining_class == 0:
+            continue
+        if result and result[-1][1] == code - 1 and result[-1][2] == combining_class:
+            result[-1][1] = code
+        else:
+            result.append([code, code, combining_class])
+    return result
+
+
+def is_hangul_syllable(code):
+    return HANGUL_BASE <= code < HANGUL_BASE + HANGUL_COUNT
+
+
+def decompositions():
+    result = []
+    for code in code_points():
+        if is_hangul_syllable(code):
+            continue
+        decomposed = unicodedata.normalize("NFD", chr(code))
+        if decomposed != chr(code):
+            result.append((code, [ord(c) for c in decomposed]))
+    return result
+
+
+def compositions():
+    result = []
+    for code in code_points():
+        if is_hangul_syllable(code):
+            continue
+        mapping = unicodedata.decomposition(chr(code))
+        if not mapping or mapping.startswith("<"):
+            continue
+        parts = [int(part, 16) for part in mapping.split()]
+        if len(parts) != 2:
+            continue
+        # Characters excluded from composition don't survive normalisation.
+        if unicodedata.normalize("NFC", chr(code)) != chr(code):
+            continue
+        result.append(((parts[0], parts[1]), code))
+    return sorted(result)
+
+
+def char(code):
+    return "'\\u{{{:x}}}'".format(code)
+
+
+def main():
+    out = sys.stdout
+    out.write("// Generated by tools/generate_unicode_tables.py from Unicode {}. Do not edit.\n\n"
+              .format(unicodedata.unidata_version))
+
+    for name, predicate in [
+        ("XID_START", lambda code: chr(code).isidentifier() and code != ord("_")),
+        ("XID_CONTINUE", lambda code: ("a" + chr(code)).isidentifier()),
+    ]:
+        out.write("pub(crate) const {}: &[(char, char)] = &[\n".format(name))
+        for begin, end in ranges(predicate):
+            out.write("    ({}, {}),\n".format(char(begin), char(end)))
+        out.write("];\n\n")
+
+    out.write("pub(crate) const CANONICAL_COMBINING_CLASS: &[(char, char, u8)] = &[\n")
+    for begin, end, combining_class in class_ranges():
+        out.write("    ({}, {}, {}),\n".format(char(begin), char(end), combining_class))
+    out.write("];\n\n")
+
+    out.write("pub(crate) const CANONICAL_DECOMPOSITION: &[(char, &[char])] = &[\n")
+    for code, decomposed in decompositions():
+        out.write("    ({}, &[{}]),\n".format(char(code), ", ".join(char(c) for c in decomposed)))
+    out.write("];\n\n")
+
+    out.write("pub(crate) const CANONICAL_COMPOSITION: &[((char, char), char)] = &[\n")
+    for (first, second), code in compositions():
+        out.write("    (({}, {}), {}),\n".format(char(first), char(second), char(code)))
+    out.write("];\n")
+
+
+if __name__ == "__main__":
+    main()